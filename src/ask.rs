@@ -0,0 +1,157 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+/// Split `/ask` arguments into the schema and the question. The schema follows `--schema`
+/// as a JSON object, optionally in single or double quotes:
+/// `--schema '{"pros": [], "cons": [], "verdict": ""}' Should we use SQLite?`
+pub fn parse_args(args: &str) -> Result<(Map<String, Value>, String)> {
+    let usage = "Usage: /ask --schema '{\"field\": example, ...}' <question>";
+    let rest = args.trim().strip_prefix("--schema").ok_or_else(|| anyhow::anyhow!(usage))?.trim_start();
+    let (schema, question) = match rest.chars().next() {
+        Some(quote @ ('\'' | '"')) => rest[1..].split_once(quote).ok_or_else(|| anyhow::anyhow!("The schema's closing {} is missing", quote))?,
+        Some('{') => rest.split_at(object_end(rest).ok_or_else(|| anyhow::anyhow!("The schema's closing }} is missing"))?),
+        _ => return Err(anyhow::anyhow!(usage)),
+    };
+    let schema = match serde_json::from_str(schema) {
+        Ok(Value::Object(fields)) if !fields.is_empty() => fields,
+        Ok(_) => return Err(anyhow::anyhow!("The schema must be a JSON object with at least one field")),
+        Err(e) => return Err(anyhow::anyhow!("The schema is not valid JSON: {}", e)),
+    };
+    let question = question.trim();
+    if question.is_empty() {
+        return Err(anyhow::anyhow!(usage));
+    }
+    Ok((schema, question.to_string()))
+}
+
+// Where the object at the start of `text` ends, skipping braces inside strings
+fn object_end(text: &str) -> Option<usize> {
+    let (mut depth, mut in_string, mut escaped) = (0, false, false);
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn prompt(schema: &Map<String, Value>, question: &str) -> String {
+    format!(
+        "{}\n\nAnswer with a single JSON object with exactly these fields, shaped like this example \
+         (lists may have any number of items): {}",
+        question,
+        Value::Object(schema.clone())
+    )
+}
+
+/// The reply's fields in schema order, each checked against the kind of its example value.
+pub fn fields(schema: &Map<String, Value>, reply: &str) -> Result<Vec<(String, Value)>> {
+    let reply = reply.trim().trim_start_matches("```json").trim_matches('`').trim();
+    let Value::Object(mut object) = serde_json::from_str(reply)
+        .map_err(|e| anyhow::anyhow!("The reply is not valid JSON: {}", e))?
+    else {
+        return Err(anyhow::anyhow!("The reply is not a JSON object"));
+    };
+    schema.iter()
+        .map(|(name, example)| {
+            let value = object.remove(name).ok_or_else(|| anyhow::anyhow!("The reply has no '{}' field", name))?;
+            let matches = match example {
+                Value::Null => true,
+                Value::Bool(_) => value.is_boolean(),
+                Value::Number(_) => value.is_number(),
+                Value::String(_) => value.is_string(),
+                Value::Array(_) => value.is_array(),
+                Value::Object(_) => value.is_object(),
+            };
+            if !matches {
+                return Err(anyhow::anyhow!("'{}' should be {} like {}, not {}", name, kind(example), example, value));
+            }
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "anything",
+        Value::Bool(_) => "true or false",
+        Value::Number(_) => "a number",
+        Value::String(_) => "text",
+        Value::Array(_) => "a list",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// A markdown section per field: text as a paragraph, lists as bullets.
+pub fn render(fields: &[(String, Value)]) -> String {
+    let mut markdown = String::new();
+    for (name, value) in fields {
+        let heading = name.replace(['_', '-'], " ");
+        let mut chars = heading.chars();
+        let heading: String = chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default();
+        markdown.push_str(&format!("## {}\n", heading));
+        match value {
+            Value::Array(items) if items.is_empty() => markdown.push_str("*None*\n"),
+            Value::Array(items) => {
+                for item in items {
+                    markdown.push_str(&format!("- {}\n", text(item)));
+                }
+            }
+            Value::Object(object) => {
+                for (key, item) in object {
+                    markdown.push_str(&format!("- **{}**: {}\n", key, text(item)));
+                }
+            }
+            value => markdown.push_str(&format!("{}\n", text(value))),
+        }
+        markdown.push('\n');
+    }
+    markdown
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let (schema, question) = parse_args(r#"--schema '{"pros": [], "verdict": ""}' Rust or Go?"#).unwrap();
+        assert_eq!(schema.keys().collect::<Vec<_>>(), ["pros", "verdict"]);
+        assert_eq!(question, "Rust or Go?");
+        let (schema, _) = parse_args(r#"--schema {"note": "a } b"} why"#).unwrap();
+        assert_eq!(schema["note"], "a } b");
+        assert!(parse_args("Rust or Go?").is_err());
+        assert!(parse_args(r#"--schema '{"pros": []}'"#).is_err());
+    }
+
+    #[test]
+    fn test_fields() {
+        let (schema, _) = parse_args(r#"--schema '{"pros": [], "cons": [], "verdict": ""}' q"#).unwrap();
+        let reply = r#"{"verdict": "Use Rust", "cons": [], "pros": ["fast", "safe"], "extra": 1}"#;
+        let fields = fields(&schema, reply).unwrap();
+        assert_eq!(
+            render(&fields),
+            "## Pros\n- fast\n- safe\n\n## Cons\n*None*\n\n## Verdict\nUse Rust\n\n"
+        );
+        let error = super::fields(&schema, r#"{"pros": "fast", "cons": [], "verdict": ""}"#).unwrap_err();
+        assert_eq!(error.to_string(), r#"'pros' should be a list like [], not "fast""#);
+        assert!(super::fields(&schema, r#"{"pros": []}"#).is_err());
+    }
+}
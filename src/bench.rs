@@ -0,0 +1,148 @@
+use anyhow::Result;
+use futures::StreamExt;
+use std::time::{Duration, Instant};
+use crate::llama::{self, LlamaClient, Message, StreamEvent};
+
+pub const DEFAULT_PROMPT: &str =
+    "Explain in about 150 words how a hash map works, including what happens on a collision.";
+pub const DEFAULT_RUNS: usize = 3;
+
+/// Timings of one streamed reply.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub first_token: Duration,
+    pub total: Duration,
+    pub tokens: usize,
+}
+
+impl Sample {
+    /// Generation speed after the first token arrived, so queueing and prompt processing don't count.
+    pub fn tokens_per_sec(&self) -> f64 {
+        let generating = self.total.saturating_sub(self.first_token).as_secs_f64();
+        if generating > 0.0 { self.tokens as f64 / generating } else { 0.0 }
+    }
+}
+
+/// Stream one reply and time it.
+pub async fn run_once(client: &LlamaClient, prompt: &str) -> Result<Sample> {
+    let messages = [Message {
+        role: "user".to_string(),
+        content: prompt.to_string(),
+    }];
+    let start = Instant::now();
+    let response = client.stream(&messages).await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str(&body).ok()
+            .and_then(|json| llama::error_message(&json))
+            .unwrap_or(body);
+        return Err(llama::LlamaError::RequestFailed(format!("{}: {}", status, message)).into());
+    }
+
+    let mut stream = response.bytes_stream();
+    let mut decoder = client.stream_decoder();
+    let mut first_token = None;
+    let mut text = String::new();
+    let mut reported_tokens = None;
+    let mut ended = false;
+    while !ended {
+        let payloads = match stream.next().await {
+            Some(chunk) => decoder.push(&chunk?),
+            None => {
+                ended = true;
+                decoder.finish()
+            }
+        };
+        for event in payloads.iter().flat_map(|payload| client.parse_stream_data(payload, decoder.tool_calls())) {
+            match event {
+                // Reasoning tokens are generated output too
+                StreamEvent::Content(content) | StreamEvent::Reasoning(content) => {
+                    first_token.get_or_insert_with(|| start.elapsed());
+                    text.push_str(&content);
+                }
+                StreamEvent::Usage(usage) if usage.completion_tokens > 0 => {
+                    reported_tokens = Some(usage.completion_tokens as usize);
+                }
+                StreamEvent::Error(message) => return Err(llama::LlamaError::StreamError(message).into()),
+                _ => {}
+            }
+        }
+        if decoder.is_done() {
+            ended = true;
+        }
+    }
+
+    let total = start.elapsed();
+    Ok(Sample {
+        first_token: first_token.unwrap_or(total),
+        total,
+        // Prefer the provider's own count; fall back to the usual estimate
+        tokens: reported_tokens.unwrap_or_else(|| llama::estimate_tokens(&text)),
+    })
+}
+
+/// Aggregated results for one provider.
+pub struct Row {
+    pub provider: String,
+    pub model: String,
+    pub samples: Vec<Sample>,
+    pub failures: usize,
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] }
+}
+
+/// A markdown table (rendered by the usual skin) with medians over all successful runs.
+pub fn render_table(rows: &[Row]) -> String {
+    let mut table = String::from(
+        "|provider|model|runs|first token (s)|tokens/s|total (s)|\n|-|-|-:|-:|-:|-:|\n",
+    );
+    for row in rows {
+        let runs = format!("{}/{}", row.samples.len(), row.samples.len() + row.failures);
+        if row.samples.is_empty() {
+            table.push_str(&format!("|{}|{}|{}|-|-|-|\n", row.provider, row.model, runs));
+            continue;
+        }
+        let first_token = median(row.samples.iter().map(|s| s.first_token.as_secs_f64()).collect());
+        let speed = median(row.samples.iter().map(Sample::tokens_per_sec).collect());
+        let total = median(row.samples.iter().map(|s| s.total.as_secs_f64()).collect());
+        table.push_str(&format!(
+            "|{}|{}|{}|{:.2}|{:.1}|{:.2}|\n",
+            row.provider, row.model, runs, first_token, speed, total
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_uses_medians() {
+        let sample = |first: u64, total: u64, tokens| Sample {
+            first_token: Duration::from_millis(first),
+            total: Duration::from_millis(total),
+            tokens,
+        };
+        let rows = [
+            Row {
+                provider: "ollama".into(),
+                model: "phi4".into(),
+                samples: vec![sample(200, 2200, 100), sample(400, 1400, 50), sample(300, 9000, 10)],
+                failures: 1,
+            },
+            Row { provider: "openai".into(), model: "gpt-4o".into(), samples: vec![], failures: 2 },
+        ];
+        let table = render_table(&rows);
+        assert!(table.contains("|ollama|phi4|3/4|0.30|50.0|2.20|"), "{}", table);
+        assert!(table.contains("|openai|gpt-4o|0/2|-|-|-|"));
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use std::time::{Duration, Instant};
+use crate::llama::{LlamaClient, Message};
+
+/// One provider's reply to a `/compare` prompt.
+pub struct Answer {
+    pub provider: String,
+    pub model: String,
+    pub elapsed: Duration,
+    pub reply: Result<String>,
+}
+
+/// Ask every client at once and return the answers in the order the clients were given.
+pub async fn ask_all(clients: Vec<(String, LlamaClient)>, messages: &[Message]) -> Vec<Answer> {
+    let requests = clients.into_iter().map(|(provider, client)| async move {
+        let start = Instant::now();
+        let reply = client.complete(messages).await;
+        Answer {
+            provider,
+            model: client.model().to_string(),
+            elapsed: start.elapsed(),
+            reply,
+        }
+    });
+    futures::future::join_all(requests).await
+}
+
+/// Markdown with one section per provider, failed ones included.
+pub fn render(answers: &[Answer]) -> String {
+    answers.iter()
+        .map(|answer| {
+            let body = match &answer.reply {
+                Ok(text) => text.trim().to_string(),
+                Err(e) => format!("*Failed: {}*", e),
+            };
+            format!(
+                "## {} ({}, {:.1}s)\n\n{}\n",
+                answer.provider,
+                answer.model,
+                answer.elapsed.as_secs_f64(),
+                body
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n---\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::ApiKind;
+    use crate::ModelConfig;
+
+    #[tokio::test]
+    async fn test_answers_keep_provider_order() {
+        let client = LlamaClient::new(ModelConfig {
+            api_type: ApiKind::Mock,
+            model: "mock".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let messages = [Message { role: "user".to_string(), content: "hi".to_string() }];
+        let answers = ask_all(vec![("first".into(), client.clone()), ("second".into(), client)], &messages).await;
+        assert_eq!(answers.iter().map(|a| a.provider.as_str()).collect::<Vec<_>>(), ["first", "second"]);
+
+        let failed = Answer {
+            provider: "openai".into(),
+            model: "gpt-4o".into(),
+            elapsed: Duration::from_millis(1500),
+            reply: Err(anyhow::anyhow!("401")),
+        };
+        assert!(render(&[failed]).starts_with("## openai (gpt-4o, 1.5s)\n\n*Failed: 401*"));
+    }
+}
@@ -0,0 +1,178 @@
+use crossterm::style::{Color, Stylize};
+
+/// One slice of the context window, e.g. the system prompt or injected web results.
+pub struct Segment {
+    pub label: &'static str,
+    pub tokens: usize,
+    pub color: Color,
+}
+
+/// Render a horizontal bar where every segment gets a share proportional to its tokens,
+/// followed by a legend line with the absolute numbers.
+pub fn render_bar(segments: &[Segment], width: usize) -> String {
+    let total: usize = segments.iter().map(|s| s.tokens).sum();
+    if total == 0 {
+        return String::from("Context is empty");
+    }
+
+    // Non-empty segments always get at least one cell; the largest one absorbs rounding
+    let mut cells: Vec<usize> = segments.iter()
+        .map(|s| if s.tokens == 0 { 0 } else { (s.tokens * width / total).max(1) })
+        .collect();
+    let largest = (0..segments.len()).max_by_key(|&i| segments[i].tokens).unwrap_or(0);
+    let assigned: usize = cells.iter().sum();
+    cells[largest] = (cells[largest] + width).saturating_sub(assigned);
+
+    let bar: String = segments.iter()
+        .zip(&cells)
+        .map(|(segment, &n)| "█".repeat(n).with(segment.color).to_string())
+        .collect();
+
+    let legend = segments.iter()
+        .filter(|s| s.tokens > 0)
+        .map(|s| format!("{} {} {}", "■".with(s.color), s.label, s.tokens))
+        .collect::<Vec<_>>()
+        .join("  ");
+
+    format!("{}\n{}  (~{} tokens)", bar, legend, total)
+}
+
+/// Cut `text` into parts of at most `max_tokens` as measured by `count`, at line breaks
+/// where possible. A single line longer than that is cut at about four characters a token.
+pub fn split(text: &str, max_tokens: usize, count: impl Fn(&str) -> usize) -> Vec<String> {
+    let max_tokens = max_tokens.max(1);
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for line in text.lines() {
+        let mut pieces = Vec::new();
+        let mut rest = line;
+        while count(rest) > max_tokens {
+            let mut cut = (max_tokens * 4).min(rest.len());
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            pieces.push(&rest[..cut]);
+            rest = &rest[cut..];
+        }
+        pieces.push(rest);
+
+        for piece in pieces {
+            let tokens = count(piece) + 1;
+            if !current.is_empty() && current_tokens + tokens > max_tokens {
+                parts.push(std::mem::take(&mut current));
+                current_tokens = 0;
+            }
+            current.push_str(piece);
+            current.push('\n');
+            current_tokens += tokens;
+        }
+    }
+    if !current.trim().is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Request for reading part `part` of `parts` of a message too long to send whole,
+/// folding it into the summary of the parts before it.
+pub fn stuffing_prompt(part: usize, parts: usize, summary: &str, text: &str) -> String {
+    let earlier = if summary.is_empty() {
+        String::new()
+    } else {
+        format!("Notes on parts 1 to {}:\n{}\n\n", part - 1, summary)
+    };
+    format!(
+        "A message was too long to send at once, so it comes in {} parts. {}Here is part {}:\n\n{}\n\n\
+         Write notes on the message so far that someone who never sees it could respond to it from: \
+         keep facts, names, numbers and code that matter, and copy any question or instruction \
+         in it word for word. Reply with the notes only.",
+        parts, earlier, part, text
+    )
+}
+
+/// What goes to the model in place of the long message: the notes taken while reading it,
+/// and its last paragraph as written, which is usually the question.
+pub fn condensed_message(parts: usize, notes: &str, last_paragraph: Option<&str>) -> String {
+    let mut message = format!(
+        "[This message was too long for the model and was read in {} parts; these are notes on it]\n\n{}",
+        parts,
+        notes.trim()
+    );
+    if let Some(paragraph) = last_paragraph {
+        message.push_str(&format!("\n\nThe message ended with:\n\n{}", paragraph.trim()));
+    }
+    message
+}
+
+/// Which messages, given as (role, tokens, pinned), to send so they stay within `budget`
+/// tokens: the system prompt and pinned messages always, then as many of the newest as
+/// fit, starting at a question so roles still alternate. The newest is kept even when it
+/// alone is over. Returns indexes.
+pub fn trim(messages: &[(&str, usize, bool)], budget: usize) -> Vec<usize> {
+    let system = messages.first().is_some_and(|(role, _, _)| *role == "system");
+    let first = usize::from(system);
+    let mut used: usize = messages.iter()
+        .enumerate()
+        .filter(|&(i, (_, _, pinned))| i < first || *pinned)
+        .map(|(_, (_, tokens, _))| tokens)
+        .sum();
+    let mut start = messages.len();
+    while start > first {
+        let (_, tokens, pinned) = messages[start - 1];
+        if !pinned {
+            if start < messages.len() && used + tokens > budget {
+                break;
+            }
+            used += tokens;
+        }
+        start -= 1;
+    }
+    while start + 1 < messages.len() && messages[start].0 != "user" {
+        start += 1;
+    }
+    let pinned = (first..start).filter(|&i| messages[i].2);
+    (0..first).chain(pinned).chain(start..messages.len()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim() {
+        let mut messages = [
+            ("system", 10, false),
+            ("user", 50, false),
+            ("assistant", 50, false),
+            ("user", 20, false),
+            ("assistant", 30, false),
+            ("user", 10, false),
+        ];
+        assert_eq!(trim(&messages, 1000), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(trim(&messages, 70), [0, 3, 4, 5]);
+        // The oldest message that fits is an answer; it goes too rather than open the history
+        assert_eq!(trim(&messages, 60), [0, 5]);
+        assert_eq!(trim(&messages, 5), [0, 5]);
+
+        // A pinned turn stays and takes its share of the budget first
+        messages[1].2 = true;
+        messages[2].2 = true;
+        assert_eq!(trim(&messages, 170), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(trim(&messages, 150), [0, 1, 2, 5]);
+        assert_eq!(trim(&messages, 5), [0, 1, 2, 5]);
+    }
+
+    #[test]
+    fn test_split() {
+        let words = |text: &str| text.split_whitespace().count();
+        let text = "one two three\nfour five\nsix seven eight nine\n";
+        assert_eq!(split(text, 5, words), ["one two three\n", "four five\n", "six seven eight nine\n"]);
+        assert_eq!(split(text, 100, words), [text]);
+        // A line over the limit is cut even without line breaks
+        let long = "x".repeat(100);
+        let parts = split(&long, 10, |text| text.len().div_ceil(4));
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts.concat().replace('\n', ""), long);
+    }
+}
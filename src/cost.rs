@@ -0,0 +1,161 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use crate::files;
+use crate::llama::Usage;
+
+/// What a request cost, from prices in USD per million tokens.
+/// `None` when the provider has no prices; a missing side counts as free.
+pub fn price(usage: &Usage, input_price: Option<f64>, output_price: Option<f64>) -> Option<f64> {
+    if input_price.is_none() && output_price.is_none() {
+        return None;
+    }
+    let input = usage.prompt_tokens as f64 * input_price.unwrap_or(0.0);
+    let output = usage.completion_tokens as f64 * output_price.unwrap_or(0.0);
+    Some((input + output) / 1_000_000.0)
+}
+
+struct Entry {
+    provider: String,
+    model: String,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cost: Option<f64>,
+}
+
+/// Tokens and spend per provider and model, for a conversation or the whole session.
+#[derive(Default)]
+pub struct Ledger {
+    entries: Vec<Entry>,
+}
+
+impl Ledger {
+    pub fn add(&mut self, provider: &str, model: &str, usage: &Usage, cost: Option<f64>) {
+        let index = match self.entries.iter().position(|e| e.provider == provider && e.model == model) {
+            Some(index) => index,
+            None => {
+                self.entries.push(Entry {
+                    provider: provider.to_string(),
+                    model: model.to_string(),
+                    prompt_tokens: 0,
+                    completion_tokens: 0,
+                    cost: None,
+                });
+                self.entries.len() - 1
+            }
+        };
+        let entry = &mut self.entries[index];
+        entry.prompt_tokens += usage.prompt_tokens as u64;
+        entry.completion_tokens += usage.completion_tokens as u64;
+        if let Some(cost) = cost {
+            *entry.cost.get_or_insert(0.0) += cost;
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.entries.iter().filter_map(|e| e.cost).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// A markdown table; providers without prices show `-` as their cost.
+    pub fn render(&self) -> String {
+        let mut table = String::from("|provider|model|prompt tokens|completion tokens|cost (USD)|\n|-|-|-:|-:|-:|\n");
+        for e in &self.entries {
+            let cost = e.cost.map(|cost| format!("{:.4}", cost)).unwrap_or_else(|| "-".to_string());
+            table.push_str(&format!(
+                "|{}|{}|{}|{}|{}|\n",
+                e.provider, e.model, e.prompt_tokens, e.completion_tokens, cost
+            ));
+        }
+        table.push_str(&format!("|**total**||||**{:.4}**|\n", self.total()));
+        table
+    }
+}
+
+/// What was spent today, kept in the cache directory across restarts so
+/// `max_cost_per_day` holds for the whole day and not just one run.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DailySpend {
+    pub date: String,
+    pub cost: f64,
+}
+
+fn today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
+impl DailySpend {
+    fn path() -> Result<PathBuf> {
+        Ok(dirs::cache_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
+            .join("abot")
+            .join("spend.json"))
+    }
+
+    /// Today's spend; nothing if the file is missing, unreadable or from an earlier day.
+    pub fn load() -> Self {
+        let spend: Self = Self::path().ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        spend.on(&today())
+    }
+
+    // The spend as of `date`, starting over when the day changed
+    fn on(self, date: &str) -> Self {
+        if self.date == date {
+            self
+        } else {
+            Self { date: date.to_string(), cost: 0.0 }
+        }
+    }
+
+    pub fn today(&self) -> f64 {
+        if self.date == today() { self.cost } else { 0.0 }
+    }
+
+    pub fn add(&mut self, cost: f64) -> Result<()> {
+        *self = std::mem::take(self).on(&today());
+        self.cost += cost;
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        files::write_atomic(&path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_adds_up_per_model() {
+        let usage = Usage { prompt_tokens: 1000, completion_tokens: 500 };
+        let cost = price(&usage, Some(2.5), Some(10.0));
+        assert_eq!(cost, Some(0.0075));
+        assert_eq!(price(&usage, None, None), None);
+
+        let mut ledger = Ledger::default();
+        ledger.add("openai", "gpt-4o", &usage, cost);
+        ledger.add("openai", "gpt-4o", &usage, cost);
+        ledger.add("ollama", "phi4", &usage, None);
+        assert!((ledger.total() - 0.015).abs() < 1e-9);
+        let table = ledger.render();
+        assert!(table.contains("|openai|gpt-4o|2000|1000|0.0150|"), "{}", table);
+        assert!(table.contains("|ollama|phi4|1000|500|-|"));
+    }
+
+    #[test]
+    fn test_daily_spend_starts_over_each_day() {
+        let spend = DailySpend { date: "2024-05-01".into(), cost: 1.5 };
+        assert_eq!(spend.on("2024-05-01").cost, 1.5);
+        let spend = DailySpend { date: "2024-05-01".into(), cost: 1.5 };
+        assert_eq!(spend.on("2024-05-02").cost, 0.0);
+    }
+}
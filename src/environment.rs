@@ -0,0 +1,71 @@
+use std::process::Command;
+
+/// Where abot is running, for questions like "why does this build fail" that depend on it.
+/// Collected once per conversation; anything that can't be found is left out.
+#[derive(Debug, Default)]
+pub struct Environment {
+    pub os: String,
+    pub shell: Option<String>,
+    pub cwd: Option<String>,
+    pub git_branch: Option<String>,
+    pub toolchain: Vec<String>,
+}
+
+// First line of a command's stdout, if it ran and succeeded
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.lines().next().map(|line| line.trim().to_string()).filter(|line| !line.is_empty())
+}
+
+impl Environment {
+    pub fn collect() -> Self {
+        Self {
+            os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            shell: std::env::var("SHELL").or_else(|_| std::env::var("COMSPEC")).ok(),
+            cwd: std::env::current_dir().ok().map(|dir| dir.display().to_string()),
+            git_branch: output("git", &["rev-parse", "--abbrev-ref", "HEAD"]),
+            toolchain: [output("rustc", &["--version"]), output("cargo", &["--version"])]
+                .into_iter()
+                .flatten()
+                .collect(),
+        }
+    }
+
+    pub fn system_context(&self) -> String {
+        let mut context = format!("\n\nThe user's environment:\n- OS: {}\n", self.os);
+        if let Some(shell) = &self.shell {
+            context.push_str(&format!("- Shell: {}\n", shell));
+        }
+        if let Some(cwd) = &self.cwd {
+            context.push_str(&format!("- Working directory: {}\n", cwd));
+        }
+        if let Some(branch) = &self.git_branch {
+            context.push_str(&format!("- Git branch: {}\n", branch));
+        }
+        if !self.toolchain.is_empty() {
+            context.push_str(&format!("- Rust toolchain: {}\n", self.toolchain.join(", ")));
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_parts_are_left_out() {
+        let environment = Environment {
+            os: "linux x86_64".to_string(),
+            git_branch: Some("main".to_string()),
+            ..Default::default()
+        };
+        let context = environment.system_context();
+        assert!(context.contains("- OS: linux x86_64\n- Git branch: main\n"));
+        assert!(!context.contains("Shell") && !context.contains("Rust"));
+    }
+}
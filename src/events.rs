@@ -0,0 +1,105 @@
+use futures::StreamExt;
+use reqwest::Response;
+use tokio::sync::mpsc;
+use crate::llama::{LlamaClient, StreamEvent};
+
+/// What engine tasks report back. Engine code only sends these; the UI loop owns the
+/// terminal and decides how to show them, so the same engine can later drive tabs,
+/// background jobs or a server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppEvent {
+    /// A decoded piece of the reply being generated.
+    MessageChunk(StreamEvent),
+    SearchProgress(String),
+    /// A tool (web search, ...) began working on behalf of the current message.
+    ToolStarted(String),
+    Error(String),
+}
+
+impl AppEvent {
+    /// One line for the status area, for everything that isn't reply text.
+    pub fn status_line(&self) -> Option<String> {
+        match self {
+            AppEvent::MessageChunk(_) => None,
+            AppEvent::SearchProgress(status) => Some(status.clone()),
+            AppEvent::ToolStarted(tool) => Some(format!("Running {}", tool)),
+            AppEvent::Error(message) => Some(format!("Error: {}", message)),
+        }
+    }
+}
+
+pub type Sender = mpsc::UnboundedSender<AppEvent>;
+pub type Receiver = mpsc::UnboundedReceiver<AppEvent>;
+
+pub fn channel() -> (Sender, Receiver) {
+    mpsc::unbounded_channel()
+}
+
+/// Engine side of a streamed reply: decode the provider response and emit every event.
+/// Also stops once the UI drops the receiver; to cancel mid-read, abort the task.
+pub async fn stream_reply(client: LlamaClient, response: Response, events: Sender) {
+    let mut stream = response.bytes_stream();
+    let mut decoder = client.stream_decoder();
+    let mut ended = false;
+    while !ended {
+        let payloads = match stream.next().await {
+            Some(Ok(chunk)) => {
+                #[cfg(debug_assertions)]
+                log::trace!("Chunk: {}", String::from_utf8_lossy(&chunk));
+                decoder.push(&chunk)
+            }
+            Some(Err(e)) => {
+                let _ = events.send(AppEvent::Error(e.to_string()));
+                return;
+            }
+            None => {
+                ended = true;
+                decoder.finish()
+            }
+        };
+
+        for payload in &payloads {
+            for event in client.parse_stream_data(payload, decoder.tool_calls()) {
+                if events.send(AppEvent::MessageChunk(event)).is_err() {
+                    return;
+                }
+            }
+        }
+
+        // Some servers keep the connection open after announcing the end
+        if decoder.is_done() {
+            ended = true;
+        }
+    }
+    if let Some(call) = decoder.unfinished_tool_call() {
+        let _ = events.send(AppEvent::MessageChunk(StreamEvent::ToolCall(call)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::{ApiKind, Message};
+    use crate::ModelConfig;
+
+    #[tokio::test]
+    async fn test_engine_emits_chunks_until_finish() {
+        let client = LlamaClient::new(ModelConfig {
+            api_type: ApiKind::Mock,
+            model: "mock".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let messages = [Message { role: "user".to_string(), content: "hi".to_string() }];
+        let response = client.stream(&messages).await.unwrap();
+
+        let (sender, mut receiver) = channel();
+        tokio::spawn(stream_reply(client, response, sender));
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+        assert!(matches!(events.first(), Some(AppEvent::MessageChunk(StreamEvent::Content(_)))));
+        assert!(events.contains(&AppEvent::MessageChunk(StreamEvent::Finish("stop".to_string()))));
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Replace `path` with `contents` by writing a temporary file next to it and renaming it
+/// over the original, so a crash or another abot writing at the same time leaves either
+/// the old or the new file, never a mix of both.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let name = path.file_name()
+        .ok_or_else(|| anyhow::anyhow!("Not a file path: {}", path.display()))?
+        .to_string_lossy();
+    // Per process, so concurrent writers don't share a temporary file
+    let partial = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let written = File::create(&partial)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&partial, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(anyhow::anyhow!("Could not write {}: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+/// Take an exclusive lock on the lock file at `path`, waiting while another process holds
+/// it. The lock is released when the returned file is dropped.
+pub fn lock(path: &Path) -> Result<File> {
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)
+        .map_err(|e| anyhow::anyhow!("Could not open {}: {}", path.display(), e))?;
+    file.lock().map_err(|e| anyhow::anyhow!("Could not lock {}: {}", path.display(), e))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = std::env::temp_dir().join(format!("abot-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        write_atomic(&path, "old").unwrap();
+        write_atomic(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        // Nothing is left behind next to it
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let held = lock(&dir.join(".lock")).unwrap();
+        let other = File::open(dir.join(".lock")).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(held);
+        assert!(other.try_lock().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::session::{self, Snapshot, Summary};
+use crate::ChatMessage;
+
+/// Where saved conversations live, `[history] backend`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// One JSON file per conversation, next to its web cache and transcripts
+    #[default]
+    Files,
+    /// A single database, `~/.cache/abot/history.db`, for many or long conversations
+    Sqlite,
+}
+
+// Most search hits shown by /history
+const MAX_HITS: usize = 20;
+// Characters of context on each side of a match in a snippet
+const SNIPPET_CONTEXT: usize = 60;
+
+/// A message that matched a `/history` search.
+pub struct Hit {
+    pub conversation_id: String,
+    pub topic: String,
+    pub role: String,
+    /// The matching part of the message, matches in `**bold**`
+    pub snippet: String,
+}
+
+/// Saved conversations, behind `/sessions`, `/history` and `/load`.
+pub trait Store {
+    fn save(&self, snapshot: &Snapshot) -> Result<()>;
+    fn load(&self, conversation_id: &str) -> Result<Snapshot>;
+    /// Conversations with at least one question, most recently updated first;
+    /// only those whose messages mention `matching`, if given.
+    fn list(&self, matching: Option<&str>) -> Result<Vec<Summary>>;
+    /// Messages containing every word of `query`, best matches first.
+    fn search(&self, query: &str) -> Result<Vec<Hit>>;
+    /// Give a saved conversation a new id, e.g. once it has a title.
+    fn rename(&self, from: &str, to: &str) -> Result<()>;
+}
+
+// Message text around the first word of `words` found in it, or None if any word is missing
+fn snippet(content: &str, words: &[String]) -> Option<String> {
+    let lower = content.to_lowercase();
+    if words.iter().any(|word| !lower.contains(word.as_str())) {
+        return None;
+    }
+    // Lowercasing can change byte lengths, so search char by char on the original
+    let chars: Vec<char> = content.chars().collect();
+    let word: Vec<char> = words.first()?.chars().collect();
+    let start = (0..chars.len()).find(|&i| {
+        chars.len() - i >= word.len()
+            && chars[i..i + word.len()].iter().zip(&word).all(|(c, w)| c.to_lowercase().eq(w.to_lowercase()))
+    })?;
+    let end = start + word.len();
+    let from = start.saturating_sub(SNIPPET_CONTEXT);
+    let to = (end + SNIPPET_CONTEXT).min(chars.len());
+    let text = |range: &[char]| range.iter().collect::<String>().replace('\n', " ");
+    Some(format!(
+        "{}{}**{}**{}{}",
+        if from > 0 { "..." } else { "" },
+        text(&chars[from..start]),
+        text(&chars[start..end]),
+        text(&chars[end..to]),
+        if to < chars.len() { "..." } else { "" }
+    ))
+}
+
+fn search_words(query: &str) -> Vec<String> {
+    query.split_whitespace().map(str::to_lowercase).collect()
+}
+
+pub fn open(backend: Backend) -> Result<Box<dyn Store>> {
+    match backend {
+        Backend::Files => Ok(Box::new(Files)),
+        Backend::Sqlite => {
+            let dir = dirs::cache_dir()
+                .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
+                .join("abot");
+            fs::create_dir_all(&dir)?;
+            Ok(Box::new(Sqlite::open(&dir.join("history.db"))?))
+        }
+    }
+}
+
+pub struct Files;
+
+impl Store for Files {
+    fn save(&self, snapshot: &Snapshot) -> Result<()> {
+        session::save_conversation(snapshot)
+    }
+
+    fn load(&self, conversation_id: &str) -> Result<Snapshot> {
+        session::load_conversation(conversation_id)
+    }
+
+    fn list(&self, matching: Option<&str>) -> Result<Vec<Summary>> {
+        session::list(matching)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Hit>> {
+        let words = search_words(query);
+        let mut hits = Vec::new();
+        for summary in session::list(words.first().map(String::as_str))? {
+            let snapshot = session::load_conversation(&summary.conversation_id)?;
+            for message in snapshot.history.iter().filter(|msg| msg.role != "system") {
+                if let Some(snippet) = snippet(&message.content, &words) {
+                    hits.push(Hit {
+                        conversation_id: summary.conversation_id.clone(),
+                        topic: summary.topic.clone(),
+                        role: message.role.clone(),
+                        snippet,
+                    });
+                }
+            }
+        }
+        hits.truncate(MAX_HITS);
+        Ok(hits)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        session::rename_conversation(from, to)
+    }
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS conversations (
+    id TEXT PRIMARY KEY,
+    provider TEXT NOT NULL,
+    model TEXT NOT NULL,
+    recording INTEGER NOT NULL,
+    template TEXT,
+    title TEXT,
+    topic TEXT NOT NULL,
+    turns INTEGER NOT NULL,
+    updated INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS messages (
+    conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    timestamp TEXT,
+    provider TEXT,
+    -- The whole message as JSON, so bookkeeping fields don't each need a column
+    message TEXT NOT NULL,
+    PRIMARY KEY (conversation_id, position)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    content,
+    conversation_id UNINDEXED,
+    role UNINDEXED
+);";
+
+pub struct Sqlite {
+    connection: Connection,
+}
+
+impl Sqlite {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        // Databases from before generated titles lack the column
+        if connection.prepare("SELECT title FROM conversations LIMIT 0").is_err() {
+            connection.execute_batch("ALTER TABLE conversations ADD COLUMN title TEXT")?;
+        }
+        // Databases from before full-text search get their index filled once
+        connection.execute_batch(
+            "INSERT INTO messages_fts (content, conversation_id, role)
+             SELECT content, conversation_id, role FROM messages
+             WHERE role != 'system' AND NOT EXISTS (SELECT 1 FROM messages_fts)",
+        )?;
+        Ok(Self { connection })
+    }
+}
+
+impl Store for Sqlite {
+    fn save(&self, snapshot: &Snapshot) -> Result<()> {
+        let updated = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute(
+            "INSERT INTO conversations (id, provider, model, recording, template, title, topic, turns, updated)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT(id) DO UPDATE SET provider = excluded.provider, model = excluded.model,
+                 recording = excluded.recording, template = excluded.template, title = excluded.title,
+                 topic = excluded.topic, turns = excluded.turns, updated = excluded.updated",
+            params![
+                snapshot.conversation_id,
+                snapshot.provider,
+                snapshot.model,
+                snapshot.recording,
+                snapshot.template,
+                snapshot.title,
+                snapshot.topic(),
+                snapshot.turns() as i64,
+                updated
+            ],
+        )?;
+        // Replies get appended, but /continue also grows the last one in place
+        transaction.execute("DELETE FROM messages WHERE conversation_id = ?1", [&snapshot.conversation_id])?;
+        transaction.execute("DELETE FROM messages_fts WHERE conversation_id = ?1", [&snapshot.conversation_id])?;
+        for (position, message) in snapshot.history.iter().enumerate() {
+            if message.role != "system" {
+                transaction.execute(
+                    "INSERT INTO messages_fts (content, conversation_id, role) VALUES (?1, ?2, ?3)",
+                    params![message.content, snapshot.conversation_id, message.role],
+                )?;
+            }
+            transaction.execute(
+                "INSERT INTO messages (conversation_id, position, role, content, timestamp, provider, message)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    snapshot.conversation_id,
+                    position as i64,
+                    message.role,
+                    message.content,
+                    message.timestamp,
+                    message.provider,
+                    serde_json::to_string(message)?
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn load(&self, conversation_id: &str) -> Result<Snapshot> {
+        let snapshot = self.connection
+            .query_row(
+                "SELECT provider, model, recording, template, title FROM conversations WHERE id = ?1",
+                [conversation_id],
+                |row| Ok(Snapshot {
+                    conversation_id: conversation_id.to_string(),
+                    provider: row.get(0)?,
+                    model: row.get(1)?,
+                    recording: row.get(2)?,
+                    template: row.get(3)?,
+                    title: row.get(4)?,
+                    history: Vec::new(),
+                }),
+            )
+            .optional()?
+            .ok_or_else(|| anyhow::anyhow!("No saved conversation {}", conversation_id))?;
+
+        let mut statement = self.connection
+            .prepare("SELECT message FROM messages WHERE conversation_id = ?1 ORDER BY position")?;
+        let history = statement
+            .query_map([conversation_id], |row| row.get::<_, String>(0))?
+            .map(|json| Ok(serde_json::from_str::<ChatMessage>(&json?)?))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Snapshot { history, ..snapshot })
+    }
+
+    fn list(&self, matching: Option<&str>) -> Result<Vec<Summary>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, topic, turns, updated FROM conversations
+             WHERE turns > 0 AND (?1 IS NULL OR EXISTS (
+                 SELECT 1 FROM messages
+                 WHERE messages.conversation_id = conversations.id AND role != 'system'
+                     AND instr(lower(content), lower(?1)) > 0))
+             ORDER BY updated DESC",
+        )?;
+        let summaries = statement
+            .query_map([matching], |row| Ok(Summary {
+                conversation_id: row.get(0)?,
+                topic: row.get(1)?,
+                turns: row.get::<_, i64>(2)? as usize,
+                updated: UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(3)? as u64),
+            }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(summaries)
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Hit>> {
+        // Quote every word so punctuation isn't taken as FTS syntax
+        let query = query.split_whitespace()
+            .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut statement = self.connection.prepare(
+            "SELECT messages_fts.conversation_id, conversations.topic, messages_fts.role,
+                    snippet(messages_fts, 0, '**', '**', '...', 16)
+             FROM messages_fts JOIN conversations ON conversations.id = messages_fts.conversation_id
+             WHERE messages_fts MATCH ?1
+             ORDER BY rank LIMIT ?2",
+        )?;
+        let hits = statement
+            .query_map(params![query, MAX_HITS as i64], |row| Ok(Hit {
+                conversation_id: row.get(0)?,
+                topic: row.get(1)?,
+                role: row.get(2)?,
+                snippet: row.get::<_, String>(3)?.replace('\n', " "),
+            }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(hits)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        // Checked before the rows move, so a taken name leaves both as they were
+        if session::conversation_dir(to)?.exists() {
+            return Err(anyhow::anyhow!("Conversation {} already exists", to));
+        }
+        let transaction = self.connection.unchecked_transaction()?;
+        // Messages point at the old id until they are moved too
+        transaction.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+        transaction.execute("UPDATE conversations SET id = ?2 WHERE id = ?1", [from, to])?;
+        transaction.execute("UPDATE messages SET conversation_id = ?2 WHERE conversation_id = ?1", [from, to])?;
+        transaction.execute("UPDATE messages_fts SET conversation_id = ?2 WHERE conversation_id = ?1", [from, to])?;
+        transaction.commit()?;
+        // Web cache and transcripts still live in the conversation's directory
+        session::rename_conversation(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_round_trip() {
+        let store = Sqlite::open(Path::new(":memory:")).unwrap();
+        let mut snapshot = Snapshot {
+            conversation_id: "c1".to_string(),
+            provider: "mock".to_string(),
+            model: "mock".to_string(),
+            recording: false,
+            template: None,
+            title: None,
+            history: vec![ChatMessage::new("system", "Be brief"), ChatMessage::new("user", "How do lifetimes work?")],
+        };
+        store.save(&snapshot).unwrap();
+        snapshot.history.push(ChatMessage::new("assistant", "They track borrows."));
+        store.save(&snapshot).unwrap();
+
+        let loaded = store.load("c1").unwrap();
+        assert_eq!(loaded.history.len(), 3);
+        assert_eq!(loaded.history[2].content, "They track borrows.");
+        assert_eq!(store.list(None).unwrap()[0].topic, "How do lifetimes work?");
+        assert_eq!(store.list(Some("BORROWS")).unwrap().len(), 1);
+        assert!(store.list(Some("be brief")).unwrap().is_empty());
+        assert!(store.load("missing").is_err());
+
+        let hits = store.search("track").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].role.as_str(), hits[0].snippet.as_str()), ("assistant", "They **track** borrows."));
+        assert!(store.search("brief").unwrap().is_empty());
+
+        snapshot.title = Some("Rust lifetimes".to_string());
+        store.save(&snapshot).unwrap();
+        store.rename("c1", "rust-lifetimes-c1").unwrap();
+        assert_eq!(store.load("rust-lifetimes-c1").unwrap().title.as_deref(), Some("Rust lifetimes"));
+        assert_eq!(store.search("track").unwrap()[0].conversation_id, "rust-lifetimes-c1");
+        assert!(store.load("c1").is_err());
+    }
+
+    #[test]
+    fn test_snippet() {
+        let words = search_words("Borrow checker");
+        assert_eq!(snippet("The borrow checker rejects this.", &words).unwrap(), "The **borrow** checker rejects this.");
+        assert!(snippet("The borrow rules", &words).is_none());
+        let long = format!("{}needle{}", "a".repeat(100), "b".repeat(100));
+        let snippet = snippet(&long, &search_words("needle")).unwrap();
+        assert!(snippet.starts_with("...a") && snippet.ends_with("b...") && snippet.contains("**needle**"));
+    }
+}
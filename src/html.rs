@@ -0,0 +1,316 @@
+use anyhow::Result;
+use minijinja::{context, Environment};
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag};
+use pulldown_cmark::escape::escape_html;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+// Highlighted code gets prefixed classes so theme CSS can't clash with the page's own
+const CODE_CLASSES: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+pub const DEFAULT_THEME: &str = "light";
+/// Built-in page themes, each a template extending `base.html`.
+pub const THEMES: [&str; 4] = ["light", "dark", "minimal", "docs"];
+
+const BASE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{ title }}</title>
+<style>
+{% block style %}{% endblock %}
+</style>
+</head>
+<body>
+{% block body %}
+<h1>{{ title }}</h1>
+<p class="meta">Exported from abot on {{ exported }}</p>
+{% for message in messages %}
+<div class="message {{ message.role }}" id="{{ message.anchor }}">
+<div class="role">{{ message.role }}</div>
+{{ message.html|safe }}
+</div>
+{% endfor %}
+{% endblock %}
+</body>
+</html>
+"#;
+
+const LIGHT: &str = r#"{% extends "base.html" %}
+{% block style %}
+body { max-width: 50em; margin: 2em auto; padding: 0 1em; font-family: system-ui, sans-serif; line-height: 1.5; color: #222; }
+h1 { font-size: 1.4em; }
+.meta { color: #777; font-size: 0.9em; }
+.message { margin: 1.5em 0; padding: 0.5em 1em; border-radius: 6px; }
+.user { background: #eef4ff; }
+.assistant { background: #f6f6f6; }
+.role { font-weight: bold; text-transform: capitalize; }
+pre { padding: 0.8em; overflow-x: auto; border-radius: 4px; }
+code { font-family: ui-monospace, monospace; font-size: 0.9em; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; }
+{{ code_css.light }}
+{% endblock %}
+"#;
+
+const DARK: &str = r#"{% extends "base.html" %}
+{% block style %}
+body { max-width: 50em; margin: 2em auto; padding: 0 1em; font-family: system-ui, sans-serif; line-height: 1.5; color: #ddd; background: #1e1f22; }
+a { color: #8ab4f8; }
+h1 { font-size: 1.4em; }
+.meta { color: #999; font-size: 0.9em; }
+.message { margin: 1.5em 0; padding: 0.5em 1em; border-radius: 6px; }
+.user { background: #26303f; }
+.assistant { background: #2b2d31; }
+.role { font-weight: bold; text-transform: capitalize; }
+pre { padding: 0.8em; overflow-x: auto; border-radius: 4px; }
+code { font-family: ui-monospace, monospace; font-size: 0.9em; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #555; padding: 0.2em 0.6em; }
+{{ code_css.dark }}
+{% endblock %}
+"#;
+
+const MINIMAL: &str = r#"{% extends "base.html" %}
+{% block style %}
+body { max-width: 40em; margin: 3em auto; padding: 0 1em; font-family: Georgia, serif; line-height: 1.6; }
+.meta { font-style: italic; }
+.message { margin: 2em 0; }
+.role { font-variant: small-caps; text-transform: capitalize; color: #666; }
+pre { padding: 0.8em; overflow-x: auto; }
+{{ code_css.light }}
+{% endblock %}
+"#;
+
+const DOCS: &str = r##"{% extends "light.html" %}
+{% block style %}
+{{ super() }}
+body { max-width: 72em; display: flex; gap: 2em; }
+nav { flex: 0 0 16em; position: sticky; top: 1em; align-self: flex-start; max-height: 95vh; overflow-y: auto; font-size: 0.9em; }
+nav ul { list-style: none; padding-left: 0; }
+nav li { margin: 0.3em 0; }
+{% for level in range(2, 7) %}nav .level-{{ level }} { padding-left: {{ level - 1 }}em; }
+{% endfor %}
+main { flex: 1; min-width: 0; }
+{% endblock %}
+{% block body %}
+<nav>
+<strong>Contents</strong>
+<ul>
+{% for entry in toc %}<li class="level-{{ entry.level }}"><a href="#{{ entry.anchor }}">{{ entry.text }}</a></li>
+{% endfor %}
+</ul>
+</nav>
+<main>
+{{ super() }}
+</main>
+{% endblock %}
+"##;
+
+fn builtin(name: &str) -> Option<&'static str> {
+    match name {
+        "base.html" => Some(BASE),
+        "light.html" => Some(LIGHT),
+        "dark.html" => Some(DARK),
+        "minimal.html" => Some(MINIMAL),
+        "docs.html" => Some(DOCS),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+struct Message {
+    role: String,
+    html: String,
+    anchor: String,
+}
+
+/// An entry of the table of contents: a question, or a heading within a message.
+#[derive(Debug, Serialize)]
+struct Heading {
+    level: u32,
+    text: String,
+    anchor: String,
+}
+
+/// `~/.config/abot/templates`: a `<name>.html` there replaces the built-in theme of that
+/// name (or `base.html`), or adds a new theme.
+pub fn template_dir() -> Option<PathBuf> {
+    Some(dirs::home_dir()?.join(".config").join("abot").join("templates"))
+}
+
+/// Themes `/export html` accepts: the built-in ones and any in the template directory.
+pub fn themes() -> Vec<String> {
+    let mut themes: Vec<String> = THEMES.iter().map(|theme| theme.to_string()).collect();
+    if let Some(entries) = template_dir().and_then(|dir| fs::read_dir(dir).ok()) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if path.extension().is_some_and(|ext| ext == "html") && name != "base" && !themes.iter().any(|theme| theme == name) {
+                themes.push(name.to_string());
+            }
+        }
+    }
+    themes
+}
+
+/// A standalone HTML page of the conversation in the given theme, with all styles inline
+/// so the file can be shared on its own. `messages` are (role, markdown) pairs.
+pub fn page(theme: &str, title: &str, exported: &str, messages: &[(&str, &str)]) -> Result<String> {
+    render(template_dir().as_deref(), theme, title, exported, messages)
+}
+
+fn render(templates: Option<&Path>, theme: &str, title: &str, exported: &str, messages: &[(&str, &str)]) -> Result<String> {
+    let syntaxes = SyntaxSet::load_defaults_newlines();
+    let code_themes = ThemeSet::load_defaults();
+
+    let mut rendered = Vec::new();
+    let mut toc = Vec::new();
+    for (i, (role, content)) in messages.iter().enumerate() {
+        let anchor = format!("message-{}", i + 1);
+        if *role == "user" {
+            let question: String = content.lines().next().unwrap_or_default().chars().take(80).collect();
+            toc.push(Heading { level: 1, text: question, anchor: anchor.clone() });
+        }
+        let (html, headings) = markdown(content, &anchor, |lang, code| highlight(&syntaxes, lang, code));
+        toc.extend(headings);
+        rendered.push(Message { role: role.to_string(), html, anchor });
+    }
+
+    let code_css = context! {
+        light => css_for_theme_with_class_style(&code_themes.themes["InspiredGitHub"], CODE_CLASSES)?,
+        dark => css_for_theme_with_class_style(&code_themes.themes["base16-ocean.dark"], CODE_CLASSES)?,
+    };
+
+    let templates = templates.map(Path::to_path_buf);
+    let mut environment = Environment::new();
+    environment.set_loader(move |name| {
+        if let Some(path) = templates.as_ref().map(|dir| dir.join(name)).filter(|path| path.is_file()) {
+            return fs::read_to_string(&path).map(Some).map_err(|e| {
+                minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, format!("{}: {}", path.display(), e))
+            });
+        }
+        Ok(builtin(name).map(str::to_string))
+    });
+    let template = environment.get_template(&format!("{}.html", theme))
+        .map_err(|e| anyhow::anyhow!("Unknown or broken HTML theme '{}': {}", theme, e))?;
+    Ok(template.render(context! { title, exported, messages => rendered, toc, code_css })?)
+}
+
+fn highlight(syntaxes: &SyntaxSet, lang: &str, code: &str) -> Option<String> {
+    let syntax = syntaxes.find_syntax_by_token(lang).unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CODE_CLASSES);
+    for line in LinesWithEndings::from(code) {
+        generator.parse_html_for_line_which_includes_newline(line).ok()?;
+    }
+    Some(format!("<pre class=\"hl-code\"><code>{}</code></pre>\n", generator.finalize()))
+}
+
+// Render markdown, replacing each fenced code block with what `highlight` makes of it
+// (blocks it can't highlight stay plain <pre><code>) and giving headings ids for the
+// table of contents
+fn markdown(text: &str, anchor: &str, highlight: impl Fn(&str, &str) -> Option<String>) -> (String, Vec<Heading>) {
+    let mut events = Vec::new();
+    let mut headings = Vec::new();
+    let mut code: Option<(String, String)> = None;
+    let mut heading: Option<(usize, String)> = None;
+    for event in Parser::new_ext(text, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, block)) = &mut code {
+                    block.push_str(&text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                let (lang, block) = code.take().unwrap_or_default();
+                let html = highlight(&lang, &block)
+                    .unwrap_or_else(|| format!("<pre><code>{}</code></pre>\n", escape(&block)));
+                events.push(Event::Html(html.into()));
+            }
+            // The opening tag is written once the heading's text is known
+            Event::Start(Tag::Heading(_)) => {
+                heading = Some((events.len(), String::new()));
+                events.push(Event::Html("".into()));
+            }
+            Event::End(Tag::Heading(level)) => {
+                let (start, text) = heading.take().unwrap_or_default();
+                let id = format!("{}-{}", anchor, headings.len() + 1);
+                events[start] = Event::Html(format!("<h{} id=\"{}\">", level, id).into());
+                events.push(Event::Html(format!("</h{}>\n", level).into()));
+                headings.push(Heading { level: level + 1, text, anchor: id });
+            }
+            Event::Text(text) | Event::Code(text) if heading.is_some() => {
+                if let Some((_, heading)) = &mut heading {
+                    heading.push_str(&text);
+                }
+                events.push(Event::Text(text));
+            }
+            event => events.push(event),
+        }
+    }
+    let mut out = String::new();
+    html::push_html(&mut out, events.into_iter());
+    (out, headings)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    let _ = escape_html(&mut escaped, text);
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES: [(&str, &str); 2] = [
+        ("user", "How do I sort a vector?"),
+        ("assistant", "## Sorting\n\nUse `sort`:\n\n```rust\nlet mut v = vec![3, 1, 2];\nv.sort();\n```\n"),
+    ];
+
+    #[test]
+    fn test_page_highlights_code() {
+        let page = render(None, "light", "Sorting <vectors>", "2024-05-01 10:00", &MESSAGES).unwrap();
+        assert!(page.contains("<title>Sorting &lt;vectors&gt;</title>"));
+        assert!(page.contains("<div class=\"message user\" id=\"message-1\">"));
+        assert!(page.contains("<code>sort</code>"));
+        assert!(page.contains("<h2 id=\"message-2-1\">Sorting</h2>"));
+        // syntect marks up the tokens, the theme's CSS colors them
+        assert!(page.contains("<pre class=\"hl-code\">"), "{}", page);
+        assert!(page.contains("<span class=\"hl-"));
+        assert!(page.contains(".hl-code {"));
+        assert!(!page.contains("```"));
+    }
+
+    #[test]
+    fn test_docs_theme_has_contents() {
+        let page = render(None, "docs", "Sorting", "2024-05-01 10:00", &MESSAGES).unwrap();
+        assert!(page.contains("<li class=\"level-1\"><a href=\"#message-1\">How do I sort a vector?</a></li>"), "{}", page);
+        assert!(page.contains("<li class=\"level-3\"><a href=\"#message-2-1\">Sorting</a></li>"));
+        assert!(render(None, "sepia", "Sorting", "now", &MESSAGES).is_err());
+    }
+
+    #[test]
+    fn test_user_templates_override_built_ins() {
+        let dir = std::env::temp_dir().join(format!("abot-templates-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("plain.html"), "{{ title }}: {% for m in messages %}{{ m.role }} {% endfor %}").unwrap();
+        let page = render(Some(&dir), "plain", "Sorting", "now", &MESSAGES).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(page, "Sorting: user assistant ");
+    }
+}
@@ -0,0 +1,83 @@
+use crossterm::style::Stylize;
+use crate::llama::TokenLogprob;
+
+/// Tokens the model gave less than this probability are flagged.
+pub const LOW_CONFIDENCE: f64 = 0.5;
+// Below this they are shown as likely wrong rather than just uncertain
+const VERY_LOW_CONFIDENCE: f64 = 0.2;
+
+/// A run of consecutive low-confidence tokens in an answer.
+#[derive(Debug, PartialEq)]
+pub struct Span {
+    pub text: String,
+    /// Probability of the least likely token in the run
+    pub lowest: f64,
+}
+
+pub fn spans(tokens: &[TokenLogprob]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut in_span = false;
+    for token in tokens {
+        let probability = token.logprob.exp();
+        if probability >= LOW_CONFIDENCE {
+            in_span = false;
+            continue;
+        }
+        match spans.last_mut().filter(|_| in_span) {
+            Some(span) => {
+                span.text.push_str(&token.token);
+                span.lowest = span.lowest.min(probability);
+            }
+            None => spans.push(Span { text: token.token.clone(), lowest: probability }),
+        }
+        in_span = true;
+    }
+    spans
+}
+
+/// The answer with uncertain tokens in yellow and very unlikely ones in red, followed by
+/// the least confident spans.
+pub fn render(tokens: &[TokenLogprob]) -> String {
+    let mut text = String::new();
+    for token in tokens {
+        let probability = token.logprob.exp();
+        if probability < VERY_LOW_CONFIDENCE {
+            text.push_str(&token.token.as_str().red().underlined().to_string());
+        } else if probability < LOW_CONFIDENCE {
+            text.push_str(&token.token.as_str().yellow().to_string());
+        } else {
+            text.push_str(&token.token);
+        }
+    }
+
+    let mut spans = spans(tokens);
+    text.push_str(&format!(
+        "\n\n{} of {} tokens below {:.0}% confidence",
+        tokens.iter().filter(|token| token.logprob.exp() < LOW_CONFIDENCE).count(),
+        tokens.len(),
+        LOW_CONFIDENCE * 100.0
+    ));
+    spans.sort_by(|a, b| a.lowest.total_cmp(&b.lowest));
+    for span in spans.iter().filter(|span| !span.text.trim().is_empty()).take(5) {
+        text.push_str(&format!("\n  {:>3.0}%  {}", span.lowest * 100.0, span.text.trim()));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_confidence_spans() {
+        let tokens: Vec<TokenLogprob> = [("The", 0.99), (" capital", 0.9), (" is", 0.95), (" Syd", 0.3), ("ney", 0.1), (".", 0.8)]
+            .iter()
+            .map(|(token, probability)| TokenLogprob { token: token.to_string(), logprob: f64::ln(*probability) })
+            .collect();
+        let spans = spans(&tokens);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, " Sydney");
+        assert!((spans[0].lowest - 0.1).abs() < 1e-9);
+        assert!(render(&tokens).contains("2 of 6 tokens below 50% confidence"));
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::Path;
+
+/// This process's hold on a conversation: an OS lock on `instance.lock` in its cache
+/// directory. The OS releases it when abot exits, crashed or not, so nothing goes stale.
+pub struct Claim {
+    _file: File,
+}
+
+/// Claim the conversation in `dir` for this abot, or None when another running abot
+/// has it open.
+pub fn claim(dir: &Path) -> Result<Option<Claim>> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join("instance.lock");
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)
+        .map_err(|e| anyhow::anyhow!("Could not open {}: {}", path.display(), e))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(Claim { _file: file })),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(anyhow::anyhow!("Could not lock {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_claim_at_a_time() {
+        let dir = std::env::temp_dir().join(format!("abot-instances-{}", std::process::id()));
+        let first = claim(&dir).unwrap();
+        assert!(first.is_some());
+        // Another abot opens the lock file separately, which conflicts the same way
+        assert!(claim(&dir).unwrap().is_none());
+        drop(first);
+        assert!(claim(&dir).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
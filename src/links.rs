@@ -0,0 +1,160 @@
+use anyhow::Result;
+use std::process::Command;
+
+/// A file location or issue reference in a reply, which `/open` can open.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Link {
+    File { path: String, line: Option<u32> },
+    /// `#123` in the current repository, or `owner/repo#45`
+    Issue { repo: Option<String>, number: u32 },
+}
+
+impl Link {
+    pub fn label(&self) -> String {
+        match self {
+            Link::File { path, line: Some(line) } => format!("{}:{}", path, line),
+            Link::File { path, line: None } => path.clone(),
+            Link::Issue { repo: Some(repo), number } => format!("{}#{}", repo, number),
+            Link::Issue { repo: None, number } => format!("#{}", number),
+        }
+    }
+}
+
+/// File paths (`src/main.rs`, `src/main.rs:42`) and issue references in the order they
+/// first appear. Paths are only what looks like one; whether the file exists is up to the caller.
+pub fn find(text: &str) -> Vec<Link> {
+    let mut links = Vec::new();
+    for word in text.split_whitespace() {
+        let word = word
+            .trim_matches(|c: char| matches!(c, '`' | '\'' | '"' | '(' | ')' | '[' | ']' | '{' | '}' | '<' | '>' | ',' | ';' | '!' | '?' | '*'))
+            .trim_end_matches(['.', ':']);
+        if let Some(link) = issue(word).or_else(|| file(word)) {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+    }
+    links
+}
+
+fn issue(word: &str) -> Option<Link> {
+    let (repo, number) = word.split_once('#')?;
+    let number = number.parse().ok()?;
+    if repo.is_empty() {
+        return Some(Link::Issue { repo: None, number });
+    }
+    let (owner, name) = repo.split_once('/')?;
+    let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    (valid(owner) && valid(name)).then(|| Link::Issue { repo: Some(repo.to_string()), number })
+}
+
+fn file(word: &str) -> Option<Link> {
+    if word.contains("://") || word.starts_with("www.") {
+        return None;
+    }
+    // path, path:line or path:line:column
+    let mut parts = word.splitn(3, ':');
+    let path = parts.next()?;
+    let line = match parts.next() {
+        Some(line) => Some(line.parse().ok()?),
+        None => None,
+    };
+    let name = path.rsplit('/').next()?;
+    let (stem, extension) = name.rsplit_once('.')?;
+    // Needs a name and a real extension, so versions (1.2.3) and abbreviations (e.g) don't count
+    let is_extension = (1..=5).contains(&extension.len())
+        && extension.chars().all(|c| c.is_ascii_alphanumeric())
+        && extension.chars().any(|c| c.is_ascii_alphabetic());
+    if !is_extension || stem.is_empty() || (!path.contains('/') && stem.len() < 2) {
+        return None;
+    }
+    Some(Link::File { path: path.to_string(), line })
+}
+
+/// `owner/repo` of a GitHub remote URL, ssh or https.
+pub fn github_repo(remote: &str) -> Option<String> {
+    let path = remote.strip_prefix("git@github.com:")
+        .or_else(|| remote.strip_prefix("https://github.com/"))
+        .or_else(|| remote.strip_prefix("ssh://git@github.com/"))?;
+    let repo = path.trim_end_matches('/').trim_end_matches(".git");
+    (repo.split('/').count() == 2).then(|| repo.to_string())
+}
+
+/// The GitHub repository of the working directory, for bare `#123` references.
+pub fn current_repo() -> Option<String> {
+    let output = Command::new("git").args(["remote", "get-url", "origin"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    github_repo(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+/// Open a file at its line in `$VISUAL`/`$EDITOR` (vi if unset), or an issue in the browser.
+/// `repo` is where bare `#123` references point.
+pub fn open(link: &Link, repo: Option<&str>) -> Result<()> {
+    match link {
+        Link::File { path, line } => {
+            let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+            let mut words = editor.split_whitespace();
+            let program = words.next().unwrap_or("vi");
+            let mut command = Command::new(program);
+            command.args(words);
+            match line {
+                // VS Code and friends take path:line, terminal editors +line
+                Some(line) if program.ends_with("code") || program.ends_with("codium") => {
+                    command.args(["-g", &format!("{}:{}", path, line)]);
+                }
+                Some(line) => {
+                    command.arg(format!("+{}", line)).arg(path);
+                }
+                None => {
+                    command.arg(path);
+                }
+            }
+            let status = command.status().map_err(|e| anyhow::anyhow!("Could not start {}: {}", program, e))?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("{} exited with {}", program, status));
+            }
+        }
+        Link::Issue { repo: issue_repo, number } => {
+            let repo = issue_repo.as_deref().or(repo)
+                .ok_or_else(|| anyhow::anyhow!("#{} has no repository; the working directory has no GitHub remote", number))?;
+            let url = format!("https://github.com/{}/issues/{}", repo, number);
+            let opener = if cfg!(target_os = "macos") {
+                Command::new("open").arg(&url).spawn()
+            } else if cfg!(windows) {
+                Command::new("cmd").args(["/C", "start", "", &url]).spawn()
+            } else {
+                Command::new("xdg-open").arg(&url).spawn()
+            };
+            opener.map_err(|e| anyhow::anyhow!("Could not open {}: {}", url, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_links() {
+        let text = "The panic is in `src/main.rs:42` (see README.md), fixed by rust-lang/rust#45 and #123. \
+                    Use version 1.2.3, e.g. https://example.com/a.html or Cargo.toml.";
+        assert_eq!(find(text), [
+            Link::File { path: "src/main.rs".to_string(), line: Some(42) },
+            Link::File { path: "README.md".to_string(), line: None },
+            Link::Issue { repo: Some("rust-lang/rust".to_string()), number: 45 },
+            Link::Issue { repo: None, number: 123 },
+            Link::File { path: "Cargo.toml".to_string(), line: None },
+        ]);
+        assert_eq!(find(text)[0].label(), "src/main.rs:42");
+    }
+
+    #[test]
+    fn test_github_repo() {
+        assert_eq!(github_repo("git@github.com:mvccn/abot.git").as_deref(), Some("mvccn/abot"));
+        assert_eq!(github_repo("https://github.com/mvccn/abot").as_deref(), Some("mvccn/abot"));
+        assert_eq!(github_repo("https://gitlab.com/mvccn/abot.git"), None);
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use crate::files;
+
+/// Named sequences of input lines (messages and commands), recorded with
+/// `/macro record <name>` and replayed by typing `@<name>`. Kept in
+/// `~/.config/abot/macros.json`; a default set has no file and lives for the session.
+#[derive(Default)]
+pub struct Macros {
+    path: Option<PathBuf>,
+    macros: BTreeMap<String, Vec<String>>,
+    recording: Option<(String, Vec<String>)>,
+}
+
+impl Macros {
+    pub fn load() -> Result<Self> {
+        let path = dirs::home_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
+            .join(".config")
+            .join("abot")
+            .join("macros.json");
+        let macros = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { path: Some(path), macros, recording: None })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn start(&mut self, name: &str) {
+        self.recording = Some((name.to_string(), Vec::new()));
+    }
+
+    /// Add a line to the macro being recorded, if any.
+    pub fn record(&mut self, line: &str) {
+        if let Some((name, lines)) = &mut self.recording {
+            // A macro that replays itself would never end
+            if line.strip_prefix('@') != Some(name.as_str()) {
+                lines.push(line.to_string());
+            }
+        }
+    }
+
+    /// Finish recording; returns the name and number of lines saved.
+    pub fn stop(&mut self) -> Result<Option<(String, usize)>> {
+        let Some((name, lines)) = self.recording.take() else {
+            return Ok(None);
+        };
+        let count = lines.len();
+        self.macros.insert(name.clone(), lines);
+        self.save()?;
+        Ok(Some((name, count)))
+    }
+
+    /// The lines to replay for `@name` input.
+    pub fn expand(&self, input: &str) -> Option<&[String]> {
+        let name = input.strip_prefix('@')?;
+        self.macros.get(name).map(Vec::as_slice)
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.macros.iter()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if self.macros.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        files::write_atomic(path, serde_json::to_string_pretty(&self.macros)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_expand() {
+        let mut macros = Macros::default();
+        macros.start("review");
+        macros.record("/new code-review");
+        macros.record("@review");
+        macros.record("/save");
+        assert_eq!(macros.stop().unwrap(), Some(("review".to_string(), 2)));
+
+        assert_eq!(macros.expand("@review").unwrap(), ["/new code-review", "/save"]);
+        assert!(macros.expand("@web").is_none());
+        assert!(macros.expand("review").is_none());
+    }
+}
@@ -1,20 +1,51 @@
 use anyhow::Result;
 use futures::StreamExt;
 use rustyline::DefaultEditor;
-use serde_json::Value;
 use termimad::MadSkin;
-use crossterm::{
-    execute,
-    terminal::{Clear, ClearType},
-    cursor,
-};
+use crossterm::style::Stylize;
 use std::io::{stdout, Write};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use uuid::Uuid;
-use log::{trace, debug, info, warn, error};
+use log::{debug, info, warn};
 mod web_search;
 mod llama;
+mod tts;
+mod context;
+mod pool;
+mod stream;
+mod memory;
+mod settings;
+mod signing;
+mod bench;
+mod probe;
+mod transcript;
+mod mock;
+mod sink;
+mod shutdown;
+mod secrets;
+mod events;
+mod session;
+mod redact;
+mod share;
+mod environment;
+mod compare;
+mod cost;
+mod translate;
+mod proofread;
+mod macros;
+mod history;
+mod typeahead;
+mod watchdog;
+mod html;
+mod inspect;
+mod pdf;
+mod links;
+mod render;
+mod files;
+mod instances;
+mod ask;
 use web_search::WebSearch;
 
 
@@ -22,6 +53,121 @@ use web_search::WebSearch;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct WebSearchConfig {
     result_limit: usize,
+    /// Pages larger than this are summarized chunk by chunk
+    #[serde(default = "default_max_page_tokens")]
+    max_page_tokens: usize,
+    /// Strip emails, IP addresses, internal hostnames and `redact_terms` from queries
+    #[serde(default)]
+    redact_queries: bool,
+    /// Names and other words that must never reach a search engine
+    #[serde(default)]
+    redact_terms: Vec<String>,
+    /// Private domains besides .internal, .local, .lan, .corp, ...
+    #[serde(default)]
+    internal_domains: Vec<String>,
+}
+
+fn default_max_page_tokens() -> usize {
+    3000
+}
+
+/// Text-to-speech backend used by `/export audio` (OpenAI `/audio/speech` compatible)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TtsConfig {
+    api_url: String,
+    api_key: Option<String>,
+    model: String,
+    voice: String,
+    /// mp3, wav, opus, flac, ...
+    format: String,
+}
+
+impl Default for TtsConfig {
+    fn default() -> Self {
+        Self {
+            api_url: String::from("https://api.openai.com/v1/audio/speech"),
+            api_key: Some(String::from("your-openai-key")),
+            model: String::from("tts-1"),
+            voice: String::from("alloy"),
+            format: String::from("mp3"),
+        }
+    }
+}
+
+/// Connection settings shared by the LLM and web search clients
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct NetworkConfig {
+    /// e.g. `http://proxy.corp:3128`; falls back to `HTTP_PROXY`/`HTTPS_PROXY`
+    proxy: Option<String>,
+    /// Comma separated hosts that bypass the proxy; falls back to `NO_PROXY`
+    no_proxy: Option<String>,
+    /// Hostname to IP overrides that skip DNS, e.g. `"llm.corp" = "10.0.0.5"`
+    #[serde(default)]
+    resolve: HashMap<String, String>,
+    /// Try IPv4 addresses before IPv6 ones, for networks with broken IPv6 routes;
+    /// hosts with only IPv6 still work
+    #[serde(default)]
+    prefer_ipv4: bool,
+}
+
+/// HMAC request signing for self-hosted gateways that require it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct SigningConfig {
+    /// Shared secret configured on the gateway
+    secret: String,
+    /// Seconds our clock may differ from the gateway's before requests are re-signed with its time
+    #[serde(default = "default_max_clock_skew")]
+    max_clock_skew: u64,
+}
+
+fn default_max_clock_skew() -> u64 {
+    300
+}
+
+/// Long-term facts about the user, see `/remember` and `/memory`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct MemoryConfig {
+    /// Let the model propose facts with `REMEMBER:` lines; each is saved only once confirmed
+    auto_remember: bool,
+}
+
+/// A pre-configured kind of conversation, started with `abot new --template <name>` or `/new <name>`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct TemplateConfig {
+    /// Replaces `initial_prompt` for conversations started from this template
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    provider: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+}
+
+/// Where saved conversations are kept
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct HistoryConfig {
+    #[serde(default)]
+    backend: history::Backend,
+}
+
+/// Budgets that need confirmation to exceed
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct LimitsConfig {
+    /// USD per day across all providers with prices
+    #[serde(default)]
+    max_cost_per_day: Option<f64>,
+    /// Prompt tokens of a single request, history included
+    #[serde(default)]
+    max_tokens_per_request: Option<usize>,
+}
+
+/// Spelling and grammar fixes for prompts before they are sent
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct ProofreadConfig {
+    enabled: bool,
+    /// A local or cheap provider for the pass; the current one when unset
+    #[serde(default)]
+    provider: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,16 +178,188 @@ struct Config {
     openai: ModelConfig,
     llamacpp: ModelConfig,
     ollama: ModelConfig,
+    #[serde(default = "default_claude_config")]
+    claude: ModelConfig,
+    #[serde(default = "default_gemini_config")]
+    gemini: ModelConfig,
+    #[serde(default = "default_azure_config")]
+    azure: ModelConfig,
+    #[serde(default = "default_mistral_config")]
+    mistral: ModelConfig,
+    #[serde(default = "default_groq_config")]
+    groq: ModelConfig,
+    #[serde(default = "default_together_config")]
+    together: ModelConfig,
+    #[serde(default = "default_grok_config")]
+    grok: ModelConfig,
+    #[serde(default = "default_mock_config")]
+    mock: ModelConfig,
+    /// Any number of extra endpoints, e.g. `[providers.lmstudio]`
+    #[serde(default)]
+    providers: HashMap<String, ModelConfig>,
     web_search: WebSearchConfig,
+    #[serde(default)]
+    tts: TtsConfig,
+    #[serde(default)]
+    memory: MemoryConfig,
+    #[serde(default)]
+    network: NetworkConfig,
+    #[serde(default)]
+    proofread: ProofreadConfig,
+    #[serde(default)]
+    limits: LimitsConfig,
+    #[serde(default)]
+    history: HistoryConfig,
+    /// Conversation templates, e.g. `[templates.code-review]`
+    #[serde(default)]
+    templates: HashMap<String, TemplateConfig>,
+}
+
+/// A conversation entry plus local bookkeeping that is never sent to the provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+    /// Web search results were injected into this message
+    #[serde(default)]
+    web_search: bool,
+    /// Token usage reported by the provider for the request that produced this reply
+    #[serde(default)]
+    usage: Option<llama::Usage>,
+    /// The reply stopped at the token limit and can be resumed with `/continue`
+    #[serde(default)]
+    truncated: bool,
+    /// Reasoning the model produced before this reply, shown collapsed
+    #[serde(default)]
+    thinking: Option<String>,
+    /// When the message was added, RFC 3339
+    #[serde(default)]
+    timestamp: Option<String>,
+    /// Provider that wrote this reply
+    #[serde(default)]
+    provider: Option<String>,
+    /// Per-token log probabilities of this reply (`/logprobs`), for `/inspect`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    logprobs: Vec<llama::TokenLogprob>,
+    /// Model and temperature the reply was sampled with, None for models without sampling
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    /// How long the reply took, from sending the request to its last token
+    #[serde(default)]
+    elapsed_ms: Option<u64>,
+    /// Bookmark on the turn this question starts (`/mark`)
+    #[serde(default)]
+    bookmark: Option<String>,
+    /// Always sent, however far back, when the context budget leaves out old messages (`/pin`)
+    #[serde(default)]
+    pinned: bool,
+    /// The message as sent, when it was too long for the model and `content` holds the
+    /// notes it was condensed to
+    #[serde(default)]
+    original: Option<String>,
+}
+
+impl ChatMessage {
+    fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+            web_search: false,
+            usage: None,
+            truncated: false,
+            thinking: None,
+            timestamp: Some(chrono::Local::now().to_rfc3339()),
+            provider: None,
+            logprobs: Vec::new(),
+            model: None,
+            temperature: None,
+            elapsed_ms: None,
+            bookmark: None,
+            pinned: false,
+            original: None,
+        }
+    }
+
+    /// Where a reply came from, for the dim line under it (`/provenance`):
+    /// "deepseek · deepseek-chat · web search · temperature 0.7 · 3.2s".
+    /// `web_search` is whether the question it answers had search results.
+    fn provenance(&self, web_search: bool) -> String {
+        let mut parts: Vec<String> = self.provider.iter().chain(&self.model).cloned().collect();
+        if web_search {
+            parts.push("web search".to_string());
+        }
+        if let Some(temperature) = self.temperature {
+            parts.push(format!("temperature {}", temperature));
+        }
+        if let Some(elapsed) = self.elapsed_ms {
+            parts.push(format!("{:.1}s", elapsed as f64 / 1000.0));
+        }
+        parts.join(" \u{b7} ")
+    }
+
+    fn to_message(&self) -> llama::Message {
+        llama::Message {
+            role: self.role.clone(),
+            content: self.content.clone(),
+        }
+    }
+}
+
+/// Generation settings changed for this session only (`/temp`, `/maxtokens`, `/stream`).
+/// They apply to whichever provider is current.
+#[derive(Default)]
+struct Overrides {
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    stream: Option<bool>,
 }
 
 struct ChatBot {
-    history: Vec<llama::Message>,
+    history: Vec<ChatMessage>,
     config: Config,
     current_provider: String,
     llama_client: llama::LlamaClient,
     web_search: WebSearch,
     conversation_id: String,
+    /// Result of the last /models call, so a model can be picked by number
+    listed_models: Vec<String>,
+    profile: memory::Profile,
+    /// Facts the model proposed in its last replies, waiting for a yes before they are saved
+    proposed_facts: Vec<String>,
+    /// Raw requests and responses are written to the transcript directory (`/record`)
+    recording: bool,
+    /// Replies are requested as JSON and validated before display (`/json`)
+    json_mode: bool,
+    /// Token log probabilities are requested and kept for `/inspect` (`/logprobs`)
+    logprobs: bool,
+    /// Each reply is followed by the provider, model and settings that produced it (`/provenance`)
+    show_provenance: bool,
+    /// Read-only viewers of this session over TCP (`/share`)
+    share: Option<share::Share>,
+    /// The `[templates.<name>]` this conversation was started from
+    template: Option<String>,
+    /// Generated after the first exchange when `auto_title` is on
+    title: Option<String>,
+    /// Collected at the start of each conversation when `environment_context` is on
+    environment: Option<environment::Environment>,
+    /// Spend since the last /new, and since abot started (`/cost`)
+    conversation_cost: cost::Ledger,
+    session_cost: cost::Ledger,
+    /// Spend across runs, checked against `max_cost_per_day`
+    daily_spend: cost::DailySpend,
+    /// Saved conversations (`/sessions`, `/history`, `/load`)
+    store: Box<dyn history::Store>,
+    /// Keeps other abot instances from opening this conversation too
+    claim: Option<instances::Claim>,
+    overrides: Overrides,
+    /// Conversation ids from the last /sessions or /history, so one can be loaded by number
+    listed_sessions: Vec<String>,
+    /// Polls a local backend that stopped answering until it is back
+    watchdog: watchdog::Watchdog,
+    /// Files and issues the last reply mentions (`/open`)
+    links: Vec<links::Link>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -50,6 +368,56 @@ struct DefaultConfig {
     max_tokens: u32,
     stream: bool,
     initial_prompt: String,
+    /// Write every raw request and response under the conversation's cache directory
+    #[serde(default)]
+    record_transcripts: bool,
+    /// Ask for JSON replies from the start, like `/json on`
+    #[serde(default)]
+    json_mode: bool,
+    /// Ask for token log probabilities from the start, like `/logprobs on`
+    #[serde(default)]
+    logprobs: bool,
+    /// Inputs above this many tokens are previewed and need confirmation (0 = never)
+    #[serde(default = "default_paste_guard_tokens")]
+    paste_guard_tokens: usize,
+    /// Most tokens sent per request; the oldest messages are left out beyond it, never the
+    /// system prompt. Defaults to the provider's `context_length` less room for the reply
+    #[serde(default)]
+    context_budget: Option<usize>,
+    /// Tell the model the OS, shell, working directory, git branch and Rust toolchain
+    #[serde(default)]
+    environment_context: bool,
+    /// Provider for /translate; the current one when unset
+    #[serde(default)]
+    translation_provider: Option<String>,
+    /// Name each conversation after its first exchange, and its cache directory with it.
+    /// Off by default, since it costs a request to `title_provider`
+    #[serde(default)]
+    auto_title: bool,
+    /// Show which provider and model wrote each reply, with its settings and timing, under it
+    #[serde(default)]
+    show_provenance: bool,
+    /// Provider that writes the titles, ideally a cheap or local one; the current one when unset
+    #[serde(default)]
+    title_provider: Option<String>,
+    /// Switch to the provider's `fallback` on quota errors instead of only suggesting it
+    #[serde(default)]
+    auto_downgrade: bool,
+    /// Sampling defaults for providers that don't set their own
+    #[serde(default)]
+    top_p: Option<f32>,
+    #[serde(default)]
+    frequency_penalty: Option<f32>,
+    #[serde(default)]
+    presence_penalty: Option<f32>,
+    #[serde(default)]
+    stop: Option<Vec<String>>,
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+fn default_paste_guard_tokens() -> usize {
+    8000
 }
 
 impl Default for Config {
@@ -60,6 +428,22 @@ impl Default for Config {
                 max_tokens: 2000,
                 stream: true,
                 initial_prompt: String::from("You are a helpful AI assistant."),
+                record_transcripts: false,
+                json_mode: false,
+                logprobs: false,
+                paste_guard_tokens: default_paste_guard_tokens(),
+                context_budget: None,
+                environment_context: false,
+                translation_provider: None,
+                auto_title: false,
+                show_provenance: false,
+                title_provider: None,
+                auto_downgrade: false,
+                top_p: None,
+                frequency_penalty: None,
+                presence_penalty: None,
+                stop: None,
+                seed: None,
             },
             default_provider: String::from("deepseek"),
             deepseek: ModelConfig {
@@ -69,6 +453,7 @@ impl Default for Config {
                 temperature: None,  // Will use default
                 max_tokens: None,   // Will use default
                 stream: None,       // Will use default
+                ..Default::default()
             },
             openai: ModelConfig {
                 api_url: String::from("https://api.openai.com/v1/chat/completions"),
@@ -77,6 +462,7 @@ impl Default for Config {
                 temperature: None,
                 max_tokens: None,
                 stream: None,
+                ..Default::default()
             },
             llamacpp: ModelConfig {
                 api_url: String::from("http://localhost:8080/v1/chat/completions"),
@@ -85,6 +471,8 @@ impl Default for Config {
                 temperature: None,
                 max_tokens: None,
                 stream: None,
+                json_grammar: Some(true),
+                ..Default::default()
             },
             ollama: ModelConfig {
                 api_url: String::from("http://localhost:11434/api/chat"),
@@ -93,15 +481,128 @@ impl Default for Config {
                 temperature: None,
                 max_tokens: None,
                 stream: None,
+                api_type: llama::ApiKind::Ollama,
+                ..Default::default()
             },
+            claude: default_claude_config(),
+            gemini: default_gemini_config(),
+            azure: default_azure_config(),
+            mistral: default_mistral_config(),
+            groq: default_groq_config(),
+            together: default_together_config(),
+            grok: default_grok_config(),
+            mock: default_mock_config(),
+            providers: HashMap::new(),
             web_search: WebSearchConfig {
                 result_limit: 10,
+                max_page_tokens: default_max_page_tokens(),
+                redact_queries: false,
+                redact_terms: Vec::new(),
+                internal_domains: Vec::new(),
             },
+            tts: TtsConfig::default(),
+            memory: MemoryConfig::default(),
+            network: NetworkConfig::default(),
+            proofread: ProofreadConfig::default(),
+            limits: LimitsConfig::default(),
+            history: HistoryConfig::default(),
+            templates: HashMap::from([(
+                "code-review".to_string(),
+                TemplateConfig {
+                    system_prompt: Some(String::from(
+                        "You are a careful code reviewer. Point out bugs, unclear naming and missing tests, \
+                         most important first, and quote the lines you mean.",
+                    )),
+                    ..Default::default()
+                },
+            )]),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+fn default_claude_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("https://api.anthropic.com/v1/messages"),
+        api_key: Some(String::from("your-anthropic-key")),
+        model: String::from("claude-3-5-sonnet-latest"),
+        api_type: llama::ApiKind::Anthropic,
+        ..Default::default()
+    }
+}
+
+fn default_gemini_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("https://generativelanguage.googleapis.com/v1beta"),
+        api_key: Some(String::from("your-gemini-key")),
+        model: String::from("gemini-1.5-flash"),
+        api_type: llama::ApiKind::Gemini,
+        ..Default::default()
+    }
+}
+
+fn default_azure_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("https://your-resource.openai.azure.com"),
+        api_key: Some(String::from("your-azure-key")),
+        model: String::from("gpt-4o"),
+        api_type: llama::ApiKind::Azure,
+        deployment: Some(String::from("your-deployment")),
+        api_version: Some(String::from("2024-06-01")),
+        ..Default::default()
+    }
+}
+
+fn default_mistral_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("https://api.mistral.ai/v1/chat/completions"),
+        api_key: Some(String::from("your-mistral-key")),
+        model: String::from("mistral-small-latest"),
+        safe_prompt: Some(false),
+        // Mistral rejects unknown request fields and reports usage on its own
+        stream_usage: Some(false),
+        ..Default::default()
+    }
+}
+
+fn default_groq_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("https://api.groq.com/openai/v1/chat/completions"),
+        api_key: Some(String::from("your-groq-key")),
+        model: String::from("llama-3.1-8b-instant"),
+        ..Default::default()
+    }
+}
+
+// Together hosts many open-weight models; `model` takes any of their model ids
+fn default_together_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("https://api.together.xyz/v1/chat/completions"),
+        api_key: Some(String::from("your-together-key")),
+        model: String::from("meta-llama/Llama-3.3-70B-Instruct-Turbo"),
+        ..Default::default()
+    }
+}
+
+fn default_grok_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("https://api.x.ai/v1/chat/completions"),
+        api_key: Some(String::from("your-xai-key")),
+        model: String::from("grok-2-latest"),
+        ..Default::default()
+    }
+}
+
+fn default_mock_config() -> ModelConfig {
+    ModelConfig {
+        api_url: String::from("mock://local"),
+        api_key: None,
+        model: String::from("mock"),
+        api_type: llama::ApiKind::Mock,
+        ..Default::default()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 struct ModelConfig {
     api_url: String,
     api_key: Option<String>,
@@ -109,48 +610,264 @@ struct ModelConfig {
     temperature: Option<f32>,
     max_tokens: Option<u32>,
     stream: Option<bool>,
+    #[serde(default)]
+    api_type: llama::ApiKind,
+    /// Azure OpenAI deployment name
+    deployment: Option<String>,
+    /// Azure OpenAI `api-version` query parameter
+    api_version: Option<String>,
+    /// Mistral guardrail system prompt injection
+    safe_prompt: Option<bool>,
+    /// Ask OpenAI to append token usage to streams (default true)
+    stream_usage: Option<bool>,
+    /// Mock provider only: directory of canned replies, replayed in file name order
+    fixtures: Option<std::path::PathBuf>,
+    /// Largest prompt the server really accepts, in tokens (measured with /probe)
+    context_length: Option<u32>,
+    /// Tries per request on 429, 5xx and connection errors (default 3)
+    max_attempts: Option<u32>,
+    /// OpenAI organization id (`org-...`), sent as OpenAI-Organization
+    organization: Option<String>,
+    /// OpenAI project id (`proj_...`), sent as OpenAI-Project
+    project: Option<String>,
+    /// Sign every request, e.g. `signing = { secret = "..." }`
+    signing: Option<SigningConfig>,
+    /// Extra headers sent with every request, e.g. `headers = { "X-Route" = "gpu-a" }`
+    headers: Option<HashMap<String, String>>,
+    /// Proxy for this provider only, overrides `[network]`
+    proxy: Option<String>,
+    /// Extra root certificates (PEM file or bundle) for servers behind an internal CA
+    ca_cert_path: Option<std::path::PathBuf>,
+    /// Client certificate (PEM) for mutual TLS, together with `client_key_path`
+    client_cert_path: Option<std::path::PathBuf>,
+    /// PKCS#8 private key (PEM) belonging to `client_cert_path`
+    client_key_path: Option<std::path::PathBuf>,
+    /// Accept any certificate, including self-signed ones; never use over the internet
+    danger_accept_invalid_certs: Option<bool>,
+    /// Upper bound on simultaneous requests/streams to this endpoint
+    max_concurrent_requests: Option<usize>,
+    /// Requests beyond these per-minute budgets wait instead of hitting the provider's rate limit
+    requests_per_minute: Option<u32>,
+    /// Counts the prompt tokens of each request
+    tokens_per_minute: Option<u32>,
+    /// Nucleus sampling cutoff, 0 to 1
+    top_p: Option<f32>,
+    /// -2 to 2, positive values discourage repeating the same tokens
+    frequency_penalty: Option<f32>,
+    /// -2 to 2, positive values push towards new topics
+    presence_penalty: Option<f32>,
+    /// Generation stops before any of these strings
+    stop: Option<Vec<String>>,
+    /// Fixed seed for (mostly) reproducible replies
+    seed: Option<u64>,
+    /// llama.cpp: enforce JSON mode with a GBNF grammar instead of `response_format`
+    json_grammar: Option<bool>,
+    /// USD per million prompt tokens, for cost estimates and `/cost`
+    input_price: Option<f64>,
+    /// USD per million completion tokens
+    output_price: Option<f64>,
+    /// Cheaper or local provider to fall back to when this one runs out of quota or credit
+    fallback: Option<String>,
+    /// Functions offered to OpenAI-compatible models with every request; abot shows
+    /// the calls the model asks for but doesn't run them
+    tools: Option<Vec<llama::FunctionSpec>>,
 }
 
 impl ModelConfig {
-    fn get_temperature(&self, defaults: &DefaultConfig) -> f32 {
-        self.temperature.unwrap_or(defaults.temperature)
+    fn get_stream(&self, defaults: &DefaultConfig) -> bool {
+        self.stream.unwrap_or(defaults.stream)
     }
 
-    fn get_max_tokens(&self, defaults: &DefaultConfig) -> u32 {
-        self.max_tokens.unwrap_or(defaults.max_tokens)
+    /// Fill sampling parameters and streaming this provider leaves unset from `[default]`.
+    fn with_sampling_defaults(mut self, defaults: &DefaultConfig) -> Self {
+        self.stream = Some(self.get_stream(defaults));
+        self.top_p = self.top_p.or(defaults.top_p);
+        self.frequency_penalty = self.frequency_penalty.or(defaults.frequency_penalty);
+        self.presence_penalty = self.presence_penalty.or(defaults.presence_penalty);
+        self.stop = self.stop.or_else(|| defaults.stop.clone());
+        self.seed = self.seed.or(defaults.seed);
+        self
     }
+}
 
-    fn get_stream(&self, defaults: &DefaultConfig) -> bool {
-        self.stream.unwrap_or(defaults.stream)
+impl Config {
+    const BUILTIN_PROVIDERS: [&'static str; 12] = [
+        "deepseek", "openai", "llamacpp", "ollama", "claude", "gemini",
+        "azure", "mistral", "groq", "together", "grok", "mock",
+    ];
+
+    /// Look up a provider by name: built-in sections first, then `[providers.<name>]`.
+    fn provider_config(&self, name: &str) -> Option<&ModelConfig> {
+        match name {
+            "deepseek" => Some(&self.deepseek),
+            "openai" => Some(&self.openai),
+            "llamacpp" => Some(&self.llamacpp),
+            "ollama" => Some(&self.ollama),
+            "claude" | "anthropic" => Some(&self.claude),
+            "gemini" => Some(&self.gemini),
+            "azure" => Some(&self.azure),
+            "mistral" => Some(&self.mistral),
+            "groq" => Some(&self.groq),
+            "together" => Some(&self.together),
+            "grok" | "xai" => Some(&self.grok),
+            "mock" => Some(&self.mock),
+            _ => self.providers.get(name),
+        }
     }
-}
 
-impl Default for ModelConfig {
-    fn default() -> Self {
-        Self {
-            api_url: String::new(),
-            api_key: None,
-            model: String::new(),
-            temperature: None,
-            max_tokens: None,
-            stream: None,
+    fn provider_config_mut(&mut self, name: &str) -> Option<&mut ModelConfig> {
+        match name {
+            "deepseek" => Some(&mut self.deepseek),
+            "openai" => Some(&mut self.openai),
+            "llamacpp" => Some(&mut self.llamacpp),
+            "ollama" => Some(&mut self.ollama),
+            "claude" | "anthropic" => Some(&mut self.claude),
+            "gemini" => Some(&mut self.gemini),
+            "azure" => Some(&mut self.azure),
+            "mistral" => Some(&mut self.mistral),
+            "groq" => Some(&mut self.groq),
+            "together" => Some(&mut self.together),
+            "grok" | "xai" => Some(&mut self.grok),
+            "mock" => Some(&mut self.mock),
+            _ => self.providers.get_mut(name),
         }
     }
-}
 
-impl Config {
-    fn load() -> Result<Self> {
-        let config_dir = dirs::home_dir()
+    fn save(&self) -> Result<std::path::PathBuf> {
+        let path = Self::path()?;
+        files::write_atomic(&path, toml::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    fn provider_names(&self) -> Vec<String> {
+        let mut custom: Vec<_> = self.providers.keys().cloned().collect();
+        custom.sort();
+        Self::BUILTIN_PROVIDERS.iter()
+            .map(|name| name.to_string())
+            .chain(custom)
+            .collect()
+    }
+
+    /// Catch values that parse fine but can't work, before they are saved.
+    fn validate(&self) -> Result<()> {
+        if !(0.0..=2.0).contains(&self.default.temperature) {
+            return Err(anyhow::anyhow!("default.temperature must be between 0 and 2"));
+        }
+        if self.web_search.result_limit == 0 {
+            return Err(anyhow::anyhow!("web_search.result_limit must be at least 1"));
+        }
+        if self.provider_config(&self.default_provider).is_none() {
+            return Err(anyhow::anyhow!("default_provider '{}' is not a known provider", self.default_provider));
+        }
+        for name in self.provider_names() {
+            let provider = self.provider_config(&name).expect("listed providers exist");
+            url::Url::parse(&provider.api_url)
+                .map_err(|e| anyhow::anyhow!("{}.api_url is not a valid URL: {}", name, e))?;
+            if provider.temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+                return Err(anyhow::anyhow!("{}.temperature must be between 0 and 2", name));
+            }
+            if provider.top_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+                return Err(anyhow::anyhow!("{}.top_p must be between 0 and 1", name));
+            }
+            if let Some(fallback) = provider.fallback.as_deref().filter(|f| self.provider_config(f).is_none()) {
+                return Err(anyhow::anyhow!("{}.fallback '{}' is not a known provider", name, fallback));
+            }
+            let penalties = [provider.frequency_penalty, provider.presence_penalty];
+            if penalties.iter().flatten().any(|p| !(-2.0..=2.0).contains(p)) {
+                return Err(anyhow::anyhow!("{} penalties must be between -2 and 2", name));
+            }
+        }
+        if let Some(provider) = self.default.translation_provider.as_deref().filter(|p| self.provider_config(p).is_none()) {
+            return Err(anyhow::anyhow!("default.translation_provider '{}' is not a known provider", provider));
+        }
+        if let Some(provider) = self.default.title_provider.as_deref().filter(|p| self.provider_config(p).is_none()) {
+            return Err(anyhow::anyhow!("default.title_provider '{}' is not a known provider", provider));
+        }
+        if let Some(provider) = self.proofread.provider.as_deref().filter(|p| self.provider_config(p).is_none()) {
+            return Err(anyhow::anyhow!("proofread.provider '{}' is not a known provider", provider));
+        }
+        for (name, template) in &self.templates {
+            if let Some(provider) = template.provider.as_deref().filter(|p| self.provider_config(p).is_none()) {
+                return Err(anyhow::anyhow!("templates.{}.provider '{}' is not a known provider", name, provider));
+            }
+        }
+        Ok(())
+    }
+
+    // Bring config files written by older versions up to date
+    fn migrate(&mut self) {
+        // The ollama section predates api_type and always pointed at the native chat API
+        if self.ollama.api_type == llama::ApiKind::OpenAI && self.ollama.api_url.ends_with("/api/chat") {
+            self.ollama.api_type = llama::ApiKind::Ollama;
+        }
+    }
+
+    /// Fill in keys the file leaves unset (or at a `your-...` placeholder): the
+    /// `<PROVIDER>_API_KEY` environment variable first, then the system keyring. Only a
+    /// reference is stored, so saving the config never writes the secret itself.
+    fn resolve_keys(&mut self) {
+        for name in self.provider_names() {
+            let section = self.provider_config_mut(&name).expect("listed providers exist");
+            if section.api_key.as_deref().is_some_and(|key| !key.contains("your-")) {
+                continue;
+            }
+            let var = secrets::env_var(&name);
+            if std::env::var(&var).is_ok_and(|value| !value.is_empty()) {
+                section.api_key = Some(format!("{}{}", secrets::ENV_PREFIX, var));
+            } else if secrets::has_key(&name) {
+                section.api_key = Some(format!("{}{}", secrets::KEYRING_PREFIX, name));
+            }
+        }
+    }
+
+    fn path() -> Result<std::path::PathBuf> {
+        Ok(dirs::home_dir()
             .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
             .join(".config")
-            .join("abot");
-            
-        let config_path = config_dir.join("config.toml");
+            .join("abot")
+            .join("config.toml"))
+    }
+
+    /// Built-in defaults for `--safe`. If the config file on disk is broken, offer to
+    /// move it aside and write a fresh default one.
+    fn load_safe() -> Result<Self> {
+        println!("Safe mode: using built-in defaults, config and saved state are not loaded");
+        let config_path = Self::path()?;
+        let error = match fs::read_to_string(&config_path) {
+            Ok(config_str) => toml::from_str::<Config>(&config_str).err().map(|e| e.to_string()),
+            Err(e) if config_path.exists() => Some(e.to_string()),
+            Err(_) => None,
+        };
+
+        if let Some(error) = error {
+            println!("{} is broken: {}", config_path.display(), error);
+            print!("Back it up and write a fresh default config? [y/N] ");
+            stdout().flush()?;
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer)?;
+            if answer.trim().eq_ignore_ascii_case("y") {
+                let backup = config_path.with_extension(format!(
+                    "toml.bak-{}",
+                    chrono::Local::now().format("%Y%m%d_%H%M%S")
+                ));
+                fs::rename(&config_path, &backup)?;
+                files::write_atomic(&config_path, toml::to_string_pretty(&Config::default())?)?;
+                println!("Old config saved to {}", backup.display());
+                println!("New default config written to {}", config_path.display());
+            }
+        }
+
+        Ok(Config::default())
+    }
+
+    fn load() -> Result<Self> {
+        let config_path = Self::path()?;
+        let config_dir = config_path.parent().expect("config file lives in a directory");
 
         // Create config directory if it doesn't exist
         if !config_dir.exists() {
             println!("Creating config directory: {}", config_dir.display());
-            fs::create_dir_all(&config_dir)?;
+            fs::create_dir_all(config_dir)?;
         }
 
         // If config file doesn't exist, create it with default values
@@ -158,8 +875,9 @@ impl Config {
             println!("Creating default config file: {}", config_path.display());
             let default_config = Config::default();
             let toml = toml::to_string_pretty(&default_config)?;
-            fs::write(&config_path, toml)?;
-            println!("Please set your API key in the config file or DEEPSEEK_API_KEY environment variable");
+            files::write_atomic(&config_path, toml)?;
+            println!("Please set your API key in the config file, the {} environment variable or with /key set {}",
+                secrets::env_var(&default_config.default_provider), default_config.default_provider);
             println!("You can edit the config file at: {}", config_path.display());
             return Ok(default_config);
         }
@@ -167,12 +885,17 @@ impl Config {
         println!("Loading config from: {}", config_path.display());
         // Read and parse existing config file
         let config_str = fs::read_to_string(&config_path)?;
-        let config: Config = toml::from_str(&config_str)?;
+        let mut config: Config = toml::from_str(&config_str).map_err(|e| {
+            anyhow::anyhow!("Invalid config {}: {}\nRun `abot --safe` to start with defaults and repair it", config_path.display(), e)
+        })?;
+        config.migrate();
+        config.resolve_keys();
 
-        if config.deepseek.api_key.is_none() && std::env::var("DEEPSEEK_API_KEY").is_err() {
-            println!("Warning: No API key found in config file or DEEPSEEK_API_KEY environment variable");
+        let provider = config.default_provider.clone();
+        if config.provider_config(&provider).and_then(|p| p.api_key.as_deref()).is_some_and(|key| key.contains("your-")) {
+            println!("Warning: No API key found for {} in the config file, environment or keyring", provider);
             println!("Please set your API key in: {}", config_path.display());
-            println!("Or set the DEEPSEEK_API_KEY environment variable");
+            println!("Or set the {} environment variable, or run /key set {}", secrets::env_var(&provider), provider);
         }
 
         Ok(config)
@@ -180,8 +903,41 @@ impl Config {
 }
 
 impl ChatBot {
-    async fn new(config: Config) -> Result<Self> {
-        let conversation_id = Uuid::new_v4().to_string();
+    async fn new(config: Config, safe_mode: bool, fresh: bool) -> Result<Self> {
+        let snapshot = if safe_mode || fresh {
+            None
+        } else {
+            session::load().unwrap_or_else(|e| {
+                println!("{}", e);
+                None
+            })
+        };
+        // Another abot still working in the last conversation keeps it; this one starts afresh
+        let mut claim = None;
+        let snapshot = match snapshot {
+            Some(snapshot) => match session::conversation_dir(&snapshot.conversation_id).and_then(|dir| instances::claim(&dir)) {
+                Ok(Some(held)) => {
+                    claim = Some(held);
+                    Some(snapshot)
+                }
+                Ok(None) => {
+                    println!(
+                        "\"{}\" is open in another abot, so this one starts a new conversation. /load it once the other is done.",
+                        snapshot.topic()
+                    );
+                    None
+                }
+                Err(e) => {
+                    warn!("Could not check for other abot instances: {}", e);
+                    Some(snapshot)
+                }
+            },
+            None => None,
+        };
+        // A restored conversation keeps its id, and with it its web cache and transcripts
+        let conversation_id = snapshot.as_ref()
+            .map(|snapshot| snapshot.conversation_id.clone())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
         
         // Create conversation directory
         let cache_dir = dirs::cache_dir()
@@ -200,11 +956,19 @@ impl ChatBot {
         let web_search = WebSearch::new(
             &conversation_id, 
             config.web_search.result_limit,
+            config.web_search.max_page_tokens,
             llama_client_for_search
         ).await?;
 
         // Create main LlamaClient with default provider
-        let llama_client = llama::LlamaClient::new(config.deepseek.clone())?;
+        let default_config = config.provider_config(&config.default_provider)
+            .unwrap_or_else(|| {
+                println!("Unknown default provider '{}', using deepseek", config.default_provider);
+                &config.deepseek
+            })
+            .clone()
+            .with_sampling_defaults(&config.default);
+        let llama_client = llama::LlamaClient::new(default_config)?;
 
         let mut bot = Self {
             history: Vec::new(),
@@ -213,179 +977,1238 @@ impl ChatBot {
             config: config.clone(),
             web_search,
             conversation_id,
+            listed_models: Vec::new(),
+            profile: if safe_mode { memory::Profile::default() } else { memory::Profile::load()? },
+            proposed_facts: Vec::new(),
+            recording: config.default.record_transcripts,
+            json_mode: config.default.json_mode,
+            logprobs: config.default.logprobs,
+            show_provenance: config.default.show_provenance,
+            share: None,
+            template: None,
+            title: None,
+            environment: None,
+            conversation_cost: cost::Ledger::default(),
+            session_cost: cost::Ledger::default(),
+            daily_spend: cost::DailySpend::load(),
+            store: history::open(config.history.backend).unwrap_or_else(|e| {
+                println!("Could not open the {:?} history, saving conversations as files: {}", config.history.backend, e);
+                Box::new(history::Files)
+            }),
+            listed_sessions: Vec::new(),
+            watchdog: watchdog::Watchdog::default(),
+            links: Vec::new(),
+            claim,
+            overrides: Overrides::default(),
         };
+        if bot.claim.is_none() {
+            bot.claim_conversation();
+        }
+        bot.refresh_environment();
+        bot.apply_client_settings()?;
 
         // Add initial system prompt
-        let initial_prompt = bot.config.default.initial_prompt.clone();
+        let initial_prompt = bot.system_prompt();
         bot.add_message("system", &initial_prompt);
+
+        if let Some(snapshot) = snapshot {
+            let turns = bot.restore(snapshot)?;
+            if turns > 0 {
+                println!(
+                    "Restored the last session: {} turns with {} ({}). Type /new to start over.",
+                    turns,
+                    bot.current_provider,
+                    bot.llama_client.model()
+                );
+            }
+        }
         
         Ok(bot)
     }
 
-    fn add_message(&mut self, role: &str, content: &str) {
-        self.history.push(llama::Message {
-            role: role.to_string(),
-            content: content.to_string(),
-        });
+    /// The state written to the session file on exit.
+    fn snapshot(&self) -> session::Snapshot {
+        session::Snapshot {
+            conversation_id: self.conversation_id.clone(),
+            provider: self.current_provider.clone(),
+            model: self.llama_client.model().to_string(),
+            recording: self.recording,
+            template: self.template.clone(),
+            title: self.title.clone(),
+            history: self.history.clone(),
+        }
     }
 
-    fn create_custom_skin() -> MadSkin {
-        let mut skin = MadSkin::default();
-        skin.set_headers_fg(termimad::rgb(255, 187, 0));
-        skin.bold.set_fg(termimad::rgb(255, 187, 0));
-        skin.italic.set_fg(termimad::rgb(215, 255, 135));
-        skin.bullet.set_fg(termimad::rgb(255, 187, 0));
-        skin.code_block.set_fg(termimad::rgb(187, 187, 187));
-        skin.code_block.set_bg(termimad::rgb(45, 45, 45));
-        skin.quote_mark.set_fg(termimad::rgb(150, 150, 150));
-        skin
+    /// Continue a saved conversation; returns how many questions it had.
+    fn restore(&mut self, snapshot: session::Snapshot) -> Result<usize> {
+        // The provider may have been removed from the config since; keep the default then
+        if let Err(e) = self.set_provider(&snapshot.provider) {
+            println!("Could not switch back to {}: {}", snapshot.provider, e);
+        } else {
+            self.llama_client.set_model(&snapshot.model);
+        }
+        if snapshot.recording != self.recording {
+            self.recording = snapshot.recording;
+            self.apply_client_settings()?;
+        }
+        self.template = snapshot.template.filter(|name| self.config.templates.contains_key(name));
+        self.title = snapshot.title;
+        if snapshot.conversation_id != self.conversation_id {
+            self.conversation_id = snapshot.conversation_id;
+            self.conversation_cost = cost::Ledger::default();
+            self.apply_client_settings()?;
+        }
+        self.refresh_system_prompt();
+
+        // The system prompt is rebuilt from the current config and memory
+        let system = self.history.remove(0);
+        self.history = snapshot.history;
+        match self.history.first_mut().filter(|msg| msg.role == "system") {
+            Some(first) => *first = system,
+            None => self.history.insert(0, system),
+        }
+
+        Ok(self.history.iter().filter(|msg| msg.role == "user").count())
     }
 
-    async fn send_message(&mut self, message: &str) -> Result<()> {
-        let is_web_search = message.contains("@web");
+    /// Keep the conversation on disk as it grows, so `/load` can bring it back.
+    fn persist(&self) {
+        if let Err(e) = self.store.save(&self.snapshot()) {
+            warn!("Failed to save the conversation: {}", e);
+        }
+    }
 
-        let query = message
-            .split_whitespace()
-            .filter(|word| !word.starts_with('#') && !word.starts_with('@'))
-            .collect::<Vec<_>>()
-            .join(" ");
+    // Files that exist here and issues the last reply mentions; bare #123 only with a GitHub remote
+    fn find_links(&mut self) {
+        let Some(reply) = self.history.last().filter(|msg| msg.role == "assistant") else {
+            return;
+        };
+        let found = links::find(&reply.content);
+        let has_repo = found.iter().any(|link| matches!(link, links::Link::Issue { repo: None, .. }))
+            && links::current_repo().is_some();
+        self.links = found.into_iter()
+            .filter(|link| match link {
+                links::Link::File { path, .. } => std::path::Path::new(path).is_file(),
+                links::Link::Issue { repo, .. } => repo.is_some() || has_repo,
+            })
+            .collect();
+    }
 
-        let message = if is_web_search {
-            println!("Performing a web search for: '{}'", query);
-            let web_results = self.web_search.search(&query).await?;
-            format!(
-                "Based on the following web search results, please answer the question: '{}'\n\nSearch Results:\n{}",
-                query,
-                web_results
-            )
-        } else {
-            query
+    /// The provenance line of the last reply, when `/provenance` is on.
+    fn show_provenance(&self) {
+        if !self.show_provenance {
+            return;
+        }
+        let Some(index) = self.history.iter().rposition(|message| message.role == "assistant") else {
+            return;
         };
+        let web_search = index > 0 && self.history[index - 1].web_search;
+        let line = self.history[index].provenance(web_search);
+        if !line.is_empty() {
+            println!("{}", line.dim());
+        }
+    }
 
-        self.add_message("user", &message);
-        
-        // Add debug print for request
-        // println!("Sending request to: {}", self.llama_client.config.api_url);
-        
-        // Pass the entire history to generate
-        let response = match self.llama_client.generate(&self.history).await {
-            Ok(resp) => resp,
-            Err(e) => {
-                println!("Error generating response: {}", e);
-                return Err(e);
+    fn show_links(&self) {
+        if self.links.is_empty() {
+            return;
+        }
+        let labels: Vec<String> = self.links.iter()
+            .enumerate()
+            .map(|(i, link)| format!("[{}] {}", i + 1, link.label().cyan().underlined()))
+            .collect();
+        println!("{} {}", "/open:".dim(), labels.join("  "));
+    }
+
+    /// Open a file or issue from the last reply by its number in the list shown under it.
+    fn open_link(&self, number: &str) -> Result<()> {
+        let link = number.parse::<usize>().ok()
+            .and_then(|number| self.links.get(number.wrapping_sub(1)))
+            .ok_or_else(|| anyhow::anyhow!("No link {}; the last reply has {}", number, self.links.len()))?;
+        links::open(link, links::current_repo().as_deref())
+    }
+
+    // Where each turn's question is in the history; turn n starts at turns[n - 1]
+    fn turns(&self) -> Vec<usize> {
+        self.history.iter()
+            .enumerate()
+            .filter(|(_, msg)| msg.role == "user")
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Bookmark a turn, the last one unless `args` starts with its number, under the rest
+    /// of `args` as its name, or the start of its question.
+    fn mark(&mut self, args: &str) -> Result<()> {
+        let turns = self.turns();
+        let mut words = args.split_whitespace().peekable();
+        let turn = match words.peek().and_then(|word| word.parse::<usize>().ok()) {
+            Some(turn) => {
+                words.next();
+                turn
             }
+            None => turns.len(),
         };
-        
-        if self.config.default.stream {
-            // Handle streaming response
-            let mut stream = response.bytes_stream();
-            let mut current_message = String::new();
-            let mut current_block = String::new();
-            let mut rendered_length = 0;
-            let mut _lines_printed = 0;
-            let skin = Self::create_custom_skin();
+        let index = *turns.get(turn.wrapping_sub(1))
+            .ok_or_else(|| anyhow::anyhow!("No turn {}; the conversation has {}", turn, turns.len()))?;
+        let mut name = words.collect::<Vec<_>>().join(" ");
+        if name.is_empty() {
+            let question = Self::editable_prompt(&self.history[index]);
+            name = question.split_whitespace().take(6).collect::<Vec<_>>().join(" ");
+        }
+        self.history[index].bookmark = Some(name.clone());
+        self.persist();
+        println!("Bookmarked turn {} as \"{}\"; /jump {} shows it again", turn, name, name);
+        Ok(())
+    }
 
-            // Print the Assistant prefix and get initial cursor position
-            print!("Assistant: ");
-            stdout().flush()?;
-            let mut initial_position = cursor::position()?;
-            println!();  // Move to next line after the prefix
+    // Bookmarked turns in conversation order: (turn number, question index, name)
+    fn marks(&self) -> Vec<(usize, usize, &str)> {
+        self.turns().into_iter()
+            .enumerate()
+            .filter_map(|(turn, index)| Some((turn + 1, index, self.history[index].bookmark.as_deref()?)))
+            .collect()
+    }
 
-            while let Some(chunk_result) = stream.next().await {
-                let chunk = chunk_result?;
-                let chunk_str = String::from_utf8_lossy(&chunk);
+    fn show_marks(&self) {
+        let marks = self.marks();
+        if marks.is_empty() {
+            println!("No bookmarks yet. /mark bookmarks the last turn, /mark 1 problem the first one.");
+        }
+        for (i, (turn, _, name)) in marks.iter().enumerate() {
+            println!("{:>3}. {} {}", i + 1, name, format!("(turn {})", turn).dim());
+        }
+    }
 
-                #[cfg(debug_assertions)]
-                {
-                    trace!("Chunk: {}", chunk_str);
-                }
-                
-                for line in chunk_str.lines() {
-                    if line.starts_with("data: ") {
-                        let data = &line["data: ".len()..];
-                        if data == "[DONE]" { continue; }
-                        
-                        if let Ok(json) = serde_json::from_str::<Value>(data) {
-                            if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                                current_message.push_str(content);
-                                current_block.push_str(content);
-                                _lines_printed += content.matches('\n').count();
-
-                                if content.contains("\n\n") || content.contains("```") {
-                                    execute!(
-                                        stdout(),
-                                        cursor::MoveTo(initial_position.0, initial_position.1),
-                                        Clear(ClearType::FromCursorDown)
-                                    )?;
-                                    
-                                    skin.print_text(&current_message);
-                                    rendered_length = current_message.len();
-                                    current_block.clear();
-                                    
-                                    initial_position = cursor::position()?;
-                                    _lines_printed = 0;
-                                    
-                                    stdout().flush()?;
-                                } else {
-                                    if current_block.len() == content.len() {
-                                        execute!(stdout(), cursor::MoveToColumn(0))?;
-                                        _lines_printed = 0;
-                                    }
-                                    print!("{}", content);
-                                    stdout().flush()?;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    /// Show a bookmarked turn again, picked by its number in /marks or its name.
+    fn jump(&self, which: &str) -> Result<()> {
+        let marks = self.marks();
+        let words = which.to_lowercase();
+        let found = match which.parse::<usize>() {
+            Ok(number) => marks.get(number.wrapping_sub(1)),
+            Err(_) => marks.iter()
+                .find(|(_, _, name)| name.to_lowercase() == words)
+                .or_else(|| marks.iter().find(|(_, _, name)| name.to_lowercase().contains(&words))),
+        };
+        let &(turn, index, name) = found.ok_or_else(|| anyhow::anyhow!("No bookmark '{}', see /marks", which))?;
 
-            if rendered_length < current_message.len() {
-                execute!(
-                    stdout(),
-                    cursor::MoveTo(initial_position.0, initial_position.1),
-                    Clear(ClearType::FromCursorDown)
-                )?;
-                
-                skin.print_text(&current_message);
-                println!();
-            }
-            
-            self.add_message("assistant", &current_message);
-        } else {
-            // Handle non-streaming response
-            let response_text = llama::LlamaClient::get_response_text(response).await?;
+        println!("{}", format!("Turn {}: {}", turn, name).dim());
+        println!("You: {}", Self::editable_prompt(&self.history[index]));
+        if let Some(answer) = self.history.get(index + 1).filter(|msg| msg.role == "assistant") {
             println!("Assistant: ");
-            let skin = Self::create_custom_skin();
-            skin.print_text(&response_text);
-            println!();
-            self.add_message("assistant", &response_text);
+            Self::create_custom_skin().print_text(&answer.content);
         }
+        Ok(())
+    }
 
+    fn unmark(&mut self, which: &str) -> Result<()> {
+        let marks = self.marks();
+        let found = match which.parse::<usize>() {
+            Ok(number) => marks.get(number.wrapping_sub(1)),
+            Err(_) => marks.iter().find(|(_, _, name)| name.eq_ignore_ascii_case(which)),
+        };
+        let &(_, index, name) = found.ok_or_else(|| anyhow::anyhow!("No bookmark '{}', see /marks", which))?;
+        println!("Removed the bookmark \"{}\"", name);
+        self.history[index].bookmark = None;
+        self.persist();
         Ok(())
     }
 
-    fn save_last_interaction(&self) -> Result<()> {
-        if self.history.len() < 2 {
-            println!("No conversation to save yet.");
-            return Ok(());
+    /// Pin or unpin a turn, the last one unless `number` is given, so the context budget
+    /// never leaves its question and answer out.
+    fn pin(&mut self, number: Option<&str>, pinned: bool) -> Result<()> {
+        let turns = self.turns();
+        let turn = match number {
+            Some(number) => number.parse::<usize>().map_err(|_| anyhow::anyhow!("Not a turn number: {}", number))?,
+            None => turns.len(),
+        };
+        let start = *turns.get(turn.wrapping_sub(1))
+            .ok_or_else(|| anyhow::anyhow!("No turn {}; the conversation has {}", turn, turns.len()))?;
+        let end = turns.get(turn).copied().unwrap_or(self.history.len());
+        for message in &mut self.history[start..end] {
+            message.pinned = pinned;
+        }
+        self.persist();
+        let question = Self::editable_prompt(&self.history[start]);
+        let question: String = question.split_whitespace().take(8).collect::<Vec<_>>().join(" ");
+        match pinned {
+            true => println!("Pinned turn {} ({}); it is always sent, however long the conversation gets", turn, question),
+            false => println!("Unpinned turn {} ({})", turn, question),
         }
+        Ok(())
+    }
 
-        let cache_dir = dirs::cache_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
-            .join("abot")
-            .join(&self.conversation_id);
+    /// Remove the last question and everything after it, for /edit to send a revised one.
+    fn take_last_question(&mut self) -> Option<Vec<ChatMessage>> {
+        let last = self.history.iter().rposition(|msg| msg.role == "user")?;
+        Some(self.history.split_off(last))
+    }
 
-        let save_dir = cache_dir.join("save");
-        if !save_dir.exists() {
-            fs::create_dir_all(&save_dir)?;
+    // The question as the user typed it; web searches are stored with the results around it
+    fn editable_prompt(question: &ChatMessage) -> String {
+        if let Some(original) = &question.original {
+            return original.clone();
         }
+        let typed = question.content.split_once("answer the question: '")
+            .filter(|_| question.web_search)
+            .and_then(|(_, rest)| rest.split_once("'\n"))
+            .map(|(typed, _)| format!("{} @web", typed));
+        typed.unwrap_or_else(|| question.content.clone())
+    }
 
-        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-        let filename = save_dir.join(format!("interaction_{}.md", timestamp));
+    /// Carry on in a copy of the conversation, leaving the original as it is for /load.
+    fn fork(&mut self, name: Option<&str>) -> Result<()> {
+        if self.history.len() < 2 {
+            println!("Nothing to fork yet.");
+            return Ok(());
+        }
+        self.persist();
+        let original = self.snapshot().topic();
 
-        let last_user_msg = self.history.iter().rev()
-            .find(|msg| msg.role == "user")
+        let conversation_id = Uuid::new_v4().to_string();
+        self.conversation_id = match name {
+            Some(name) => session::titled_id(name, &conversation_id),
+            None => conversation_id,
+        };
+        self.claim_conversation();
+        self.title = name.map(str::to_string);
+        self.conversation_cost = cost::Ledger::default();
+        self.apply_client_settings()?;
+        self.persist();
+        println!(
+            "Forked \"{}\" into {}; the original stays in /sessions",
+            original,
+            name.map_or_else(|| "a new conversation".to_string(), |name| format!("\"{}\"", name))
+        );
+        Ok(())
+    }
+
+    /// Ask the title provider to name the conversation, then move it to a directory named
+    /// after the title so it can be told apart on disk.
+    async fn name_conversation(&mut self) -> Result<()> {
+        let snapshot = self.snapshot();
+        let Some(answer) = self.history.iter().rev().find(|msg| msg.role == "assistant") else {
+            return Ok(());
+        };
+        let provider = self.config.default.title_provider.as_deref().unwrap_or(&self.current_provider);
+        let client = self.client_for(provider)?;
+        let prompt = session::title_prompt(&snapshot.topic(), &answer.content);
+        let reply = client.complete(&[llama::Message { role: "user".to_string(), content: prompt }]).await?;
+        let Some(title) = session::clean_title(&reply) else {
+            return Ok(());
+        };
+
+        let conversation_id = session::titled_id(&title, &self.conversation_id);
+        // Let go of the directory while it moves; some systems can't rename it with the lock open
+        self.claim = None;
+        let renamed = self.store.rename(&self.conversation_id, &conversation_id);
+        if renamed.is_ok() {
+            self.conversation_id = conversation_id;
+        }
+        self.claim_conversation();
+        renamed?;
+        self.title = Some(title);
+        self.apply_client_settings()?;
+        self.persist();
+        println!("{}", format!("Conversation: {}", self.title.as_deref().unwrap_or_default()).dim());
+        Ok(())
+    }
+
+    /// Saved conversations, or only those mentioning `matching`.
+    fn list_sessions(&mut self, matching: Option<&str>) -> Result<()> {
+        let sessions = self.store.list(matching)?;
+        if sessions.is_empty() {
+            println!("{}", if matching.is_some() { "No saved conversation mentions that." } else { "No saved conversations yet." });
+        }
+        self.listed_sessions = sessions.iter().map(|s| s.conversation_id.clone()).collect();
+        for (i, summary) in sessions.iter().enumerate() {
+            let updated: chrono::DateTime<chrono::Local> = summary.updated.into();
+            let current = if summary.conversation_id == self.conversation_id { " (current)" } else { "" };
+            println!(
+                "{:>3}. {}  {} ({} turns){}",
+                i + 1,
+                updated.format("%Y-%m-%d %H:%M"),
+                summary.topic,
+                summary.turns,
+                current
+            );
+        }
+        Ok(())
+    }
+
+    /// Search every saved conversation and show the matching messages; their numbers work with /load.
+    fn search_history(&mut self, query: &str) -> Result<()> {
+        let hits = self.store.search(query)?;
+        if hits.is_empty() {
+            println!("Nothing found for '{}'.", query);
+            return Ok(());
+        }
+        let mut text = String::new();
+        self.listed_sessions = Vec::new();
+        for hit in &hits {
+            let number = match self.listed_sessions.iter().position(|id| *id == hit.conversation_id) {
+                Some(i) => i + 1,
+                None => {
+                    self.listed_sessions.push(hit.conversation_id.clone());
+                    self.listed_sessions.len()
+                }
+            };
+            text.push_str(&format!("{}. *{}* ({}): {}\n", number, hit.topic, hit.role, hit.snippet));
+        }
+        Self::create_custom_skin().print_text(&text);
+        println!("Type /load <number> to open one.");
+        Ok(())
+    }
+
+    /// Switch to a saved conversation, picked by its number in the last `/sessions` or
+    /// `/history` listing, its id, or words from its topic.
+    fn load_session(&mut self, which: &str) -> Result<()> {
+        let sessions = self.store.list(None)?;
+        let found = match which.parse::<usize>() {
+            Ok(number) if !self.listed_sessions.is_empty() => self.listed_sessions.get(number.wrapping_sub(1)).cloned(),
+            Ok(number) => sessions.get(number.wrapping_sub(1)).map(|s| s.conversation_id.clone()),
+            Err(_) => {
+                let words = which.to_lowercase();
+                sessions.iter()
+                    .find(|s| s.conversation_id.starts_with(which))
+                    .or_else(|| sessions.iter().find(|s| s.topic.to_lowercase().contains(&words)))
+                    .map(|s| s.conversation_id.clone())
+            }
+        };
+        let conversation_id = found.ok_or_else(|| anyhow::anyhow!("No saved conversation matches '{}', see /sessions", which))?;
+
+        let snapshot = self.store.load(&conversation_id)?;
+        let topic = snapshot.topic();
+        let claim = if conversation_id == self.conversation_id {
+            self.claim.take()
+        } else {
+            let claim = instances::claim(&session::conversation_dir(&conversation_id)?)?
+                .ok_or_else(|| anyhow::anyhow!("\"{}\" is open in another abot; continue it there, or /fork it there for a copy", topic))?;
+            Some(claim)
+        };
+        let turns = self.restore(snapshot)?;
+        self.claim = claim;
+        println!(
+            "Loaded \"{}\": {} turns with {} ({})",
+            topic,
+            turns,
+            self.current_provider,
+            self.llama_client.model()
+        );
+        Ok(())
+    }
+
+    /// Drop the conversation but keep settings. A template also brings its own
+    /// system prompt and, when it names them, provider and model.
+    fn new_conversation(&mut self, template: Option<&str>) -> Result<()> {
+        if let Some(name) = template {
+            let Some(settings) = self.config.templates.get(name).cloned() else {
+                let mut names: Vec<_> = self.config.templates.keys().cloned().collect();
+                names.sort();
+                return Err(anyhow::anyhow!("Unknown template '{}'. Configured: {}", name, names.join(", ")));
+            };
+            if let Some(provider) = &settings.provider {
+                self.set_provider(provider)?;
+            }
+            if let Some(model) = &settings.model {
+                self.llama_client.set_model(model);
+            }
+        }
+        self.template = template.map(str::to_string);
+        self.title = None;
+        // The previous conversation stays on disk under its own id for /load
+        self.conversation_id = Uuid::new_v4().to_string();
+        self.claim_conversation();
+        self.apply_client_settings()?;
+        self.history.truncate(1);
+        self.conversation_cost = cost::Ledger::default();
+        self.refresh_environment();
+        self.refresh_system_prompt();
+        match template {
+            Some(name) => println!(
+                "Started a new {} conversation with {} ({})",
+                name,
+                self.current_provider,
+                self.llama_client.model()
+            ),
+            None => println!("Started a new conversation"),
+        }
+        Ok(())
+    }
+
+    fn refresh_environment(&mut self) {
+        self.environment = self.config.default.environment_context.then(environment::Environment::collect);
+    }
+
+    // The configured prompt plus everything remembered about the user
+    fn system_prompt(&self) -> String {
+        let base = self.template.as_ref()
+            .and_then(|name| self.config.templates.get(name))
+            .and_then(|template| template.system_prompt.as_deref())
+            .unwrap_or(&self.config.default.initial_prompt);
+        format!(
+            "{}{}{}{}",
+            base,
+            self.profile.system_context(self.config.memory.auto_remember),
+            self.environment.as_ref().map(|environment| environment.system_context()).unwrap_or_default(),
+            // OpenAI rejects JSON mode unless the messages mention JSON
+            if self.json_mode { "\n\nReply with a single valid JSON object and nothing else." } else { "" }
+        )
+    }
+
+    // Keep the running conversation in sync after the profile changes
+    fn refresh_system_prompt(&mut self) {
+        let prompt = self.system_prompt();
+        if let Some(system) = self.history.first_mut().filter(|msg| msg.role == "system") {
+            system.content = prompt;
+        }
+    }
+
+    fn remember(&mut self, fact: &str) -> Result<()> {
+        if self.profile.remember(fact)? {
+            println!("Remembered: {}", fact.trim());
+            self.refresh_system_prompt();
+        } else {
+            println!("Already remembered");
+        }
+        Ok(())
+    }
+
+    /// Ask about each fact the model proposed; only a yes saves it, so a reply can't
+    /// slip something into every future conversation unseen.
+    fn confirm_facts(&mut self, rl: &mut DefaultEditor) {
+        for fact in std::mem::take(&mut self.proposed_facts) {
+            let Ok(answer) = rl.readline(&format!("Remember \"{}\"? [y/N] ", fact)) else {
+                break;
+            };
+            if answer.trim().eq_ignore_ascii_case("y") || answer.trim().eq_ignore_ascii_case("yes") {
+                if let Err(e) = self.remember(&fact) {
+                    println!("Error: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Expand the collapsed reasoning of the last reply.
+    fn show_thinking(&self) {
+        match self.history.iter().rev().find(|msg| msg.role == "assistant").and_then(|msg| msg.thinking.as_deref()) {
+            Some(thinking) => println!("{}", thinking.trim().dim()),
+            None => println!("The last reply has no reasoning to show."),
+        }
+    }
+
+    fn list_memory(&self) {
+        if self.profile.facts().is_empty() {
+            println!("Nothing remembered yet. Use /remember <fact> to add one.");
+            return;
+        }
+        for (i, fact) in self.profile.facts().iter().enumerate() {
+            println!("{:>3}. {}", i + 1, fact);
+        }
+    }
+
+    fn forget(&mut self, number: usize) -> Result<()> {
+        let fact = self.profile.forget(number)?;
+        println!("Forgot: {}", fact);
+        self.refresh_system_prompt();
+        Ok(())
+    }
+
+    fn add_message(&mut self, role: &str, content: &str) -> &mut ChatMessage {
+        self.history.push(ChatMessage::new(role, content));
+        self.history.last_mut().unwrap()
+    }
+
+    // The provider only ever sees role and content
+    fn request_messages(&self) -> Vec<llama::Message> {
+        self.history.iter().map(ChatMessage::to_message).collect()
+    }
+
+    /// Most tokens a request may use: `context_budget`, or what the provider's `context_length`
+    /// leaves after the reply. None when neither is known, and everything is sent.
+    fn context_budget(&self) -> Option<usize> {
+        self.config.default.context_budget.or_else(|| {
+            let limit = self.llama_client.context_length()? as usize;
+            Some(limit - self.reply_reserve(limit))
+        })
+    }
+
+    // Tokens to keep free for the reply in a context of `limit`: the token limit, but
+    // never more than a quarter of the context
+    fn reply_reserve(&self, limit: usize) -> usize {
+        let max_tokens = self.llama_client.max_tokens().unwrap_or(self.config.default.max_tokens) as usize;
+        max_tokens.min(limit / 4)
+    }
+
+    // The history as sent to the provider, without the oldest messages that don't fit
+    // the budget; also returns how many were left out
+    fn budgeted_messages(&self) -> (Vec<llama::Message>, usize) {
+        let Some(budget) = self.context_budget() else {
+            return (self.request_messages(), 0);
+        };
+        let sizes: Vec<(&str, usize, bool)> = self.history.iter()
+            .map(|msg| (msg.role.as_str(), self.count_tokens(&msg.content), msg.pinned))
+            .collect();
+        let kept = context::trim(&sizes, budget);
+        let messages = kept.iter().map(|&i| self.history[i].to_message()).collect();
+        (messages, self.history.len() - kept.len())
+    }
+
+    /// Tokens `text` takes up for the current model, counted locally.
+    fn count_tokens(&self, text: &str) -> usize {
+        llama::count_tokens(self.llama_client.model(), text)
+    }
+
+    fn show_context(&self, draft: &str) {
+        use crossterm::style::Color;
+
+        let tokens_where = |pred: &dyn Fn(&ChatMessage) -> bool| -> usize {
+            self.history.iter()
+                .filter(|msg| pred(msg))
+                .map(|msg| self.count_tokens(&msg.content))
+                .sum()
+        };
+        let segments = [
+            context::Segment {
+                label: "system",
+                tokens: tokens_where(&|msg| msg.role == "system"),
+                color: Color::Yellow,
+            },
+            context::Segment {
+                label: "history",
+                tokens: tokens_where(&|msg| msg.role != "system" && !msg.web_search),
+                color: Color::Blue,
+            },
+            context::Segment {
+                label: "web",
+                tokens: tokens_where(&|msg| msg.web_search),
+                color: Color::Green,
+            },
+            context::Segment {
+                label: "prompt",
+                tokens: self.count_tokens(draft),
+                color: Color::Magenta,
+            },
+        ];
+        println!("{}", context::render_bar(&segments, 50));
+    }
+
+    /// Offer spelling and grammar fixes for a draft, shown as a word diff. Returns the
+    /// text to send: the fixed draft if accepted, otherwise the draft as typed.
+    async fn proofread(&self, rl: &mut DefaultEditor, draft: &str) -> Result<String> {
+        // Long inputs are mostly pasted material, not something typed in a hurry
+        if !self.config.proofread.enabled || self.count_tokens(draft) > 500 {
+            return Ok(draft.to_string());
+        }
+        let provider = self.config.proofread.provider.as_deref().unwrap_or(&self.current_provider);
+        let client = self.client_for(provider)?;
+        let messages = [llama::Message {
+            role: "user".to_string(),
+            content: proofread::prompt(draft),
+        }];
+        let fixed = client.complete(&messages).await?;
+        let fixed = fixed.trim();
+        let edits = proofread::diff(draft, fixed);
+        if fixed.is_empty() || edits.iter().all(|edit| matches!(edit, proofread::Edit::Same(_))) {
+            return Ok(draft.to_string());
+        }
+
+        println!("{}", proofread::render(&edits));
+        loop {
+            let choice = rl.readline("[a]ccept the fixes or [r]eject? ")?;
+            match choice.trim().to_ascii_lowercase().as_str() {
+                "a" | "accept" | "" => return Ok(fixed.to_string()),
+                "r" | "reject" => return Ok(draft.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Preview an unusually large input and ask what to do with it; returns whether to send it.
+    /// Declined input can be kept in a file instead of being lost.
+    fn confirm_large_input(&self, rl: &mut DefaultEditor, input: &str) -> Result<bool> {
+        let limit = self.config.default.paste_guard_tokens;
+        let tokens = self.count_tokens(input);
+        if limit == 0 || tokens <= limit {
+            return Ok(true);
+        }
+
+        let lines: Vec<&str> = input.lines().collect();
+        println!("This input is ~{} tokens ({} characters, {} lines):", tokens, input.chars().count(), lines.len());
+        let preview = |line: &str| line.chars().take(100).collect::<String>();
+        for line in lines.iter().take(3) {
+            println!("  | {}", preview(line));
+        }
+        if lines.len() > 6 {
+            println!("  | ...");
+        }
+        for line in lines.iter().skip(3.max(lines.len().saturating_sub(3))) {
+            println!("  | {}", preview(line));
+        }
+        // The whole history goes along with it
+        let request: usize = tokens + self.history.iter().map(|msg| self.count_tokens(&msg.content)).sum::<usize>();
+        let price = self.config.provider_config(&self.current_provider).and_then(|p| p.input_price);
+        match price {
+            Some(price) => println!(
+                "Sending it makes a ~{} token request, about ${:.4} on {}",
+                request,
+                request as f64 * price / 1_000_000.0,
+                self.current_provider
+            ),
+            None => println!("Sending it makes a ~{} token request to {}", request, self.current_provider),
+        }
+
+        loop {
+            let choice = rl.readline("[s]end, save to [f]ile instead, or [c]ancel? ")?;
+            match choice.trim().to_ascii_lowercase().as_str() {
+                "s" | "send" => return Ok(true),
+                "f" | "file" => {
+                    let dir = self.save_dir()?;
+                    let path = dir.join(format!("paste_{}.txt", chrono::Local::now().format("%Y%m%d_%H%M%S")));
+                    files::write_atomic(&path, input)?;
+                    println!("Saved to {}, nothing was sent", path.display());
+                    return Ok(false);
+                }
+                "c" | "cancel" | "" => {
+                    println!("Cancelled, nothing was sent");
+                    return Ok(false);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn create_custom_skin() -> MadSkin {
+        let mut skin = MadSkin::default();
+        skin.set_headers_fg(termimad::rgb(255, 187, 0));
+        skin.bold.set_fg(termimad::rgb(255, 187, 0));
+        skin.italic.set_fg(termimad::rgb(215, 255, 135));
+        skin.bullet.set_fg(termimad::rgb(255, 187, 0));
+        skin.code_block.set_fg(termimad::rgb(187, 187, 187));
+        skin.code_block.set_bg(termimad::rgb(45, 45, 45));
+        skin.quote_mark.set_fg(termimad::rgb(150, 150, 150));
+        skin
+    }
+
+    async fn send_message(&mut self, message: &str) -> Result<()> {
+        let is_web_search = message.contains("@web");
+
+        let query = message
+            .split_whitespace()
+            .filter(|word| !word.starts_with('#') && !word.starts_with('@'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Some(share) = &self.share {
+            share.send(format!("\nYou: {}\n\nAssistant: ", query));
+        }
+
+        let message = if is_web_search {
+            // The search reports progress as it goes; print it while waiting for the results
+            let search_query = self.search_query(&query);
+            let (sender, mut receiver) = events::channel();
+            let (web_results, _) = tokio::join!(
+                self.web_search.search(&search_query, sender),
+                async {
+                    while let Some(event) = receiver.recv().await {
+                        Self::show_status(&event);
+                    }
+                }
+            );
+            let web_results = web_results?;
+            format!(
+                "Based on the following web search results, please answer the question: '{}'\n\nSearch Results:\n{}",
+                query,
+                web_results
+            )
+        } else {
+            query
+        };
+
+        let condensed = self.condense(&message).await?;
+        let question = self.add_message("user", condensed.as_deref().unwrap_or(&message));
+        question.web_search = is_web_search;
+        if condensed.is_some() {
+            question.original = Some(message);
+        }
+        if let Err(e) = self.answer().await {
+            // Don't leave an unanswered prompt behind; some providers require strict alternation
+            self.history.pop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// A message that can't fit the provider's `context_length` (see /probe) next to the
+    /// system prompt and the reply is read in parts, each folded into running notes, and
+    /// the notes are sent in its place. None when the message fits or the limit is unknown.
+    async fn condense(&self, message: &str) -> Result<Option<String>> {
+        let Some(limit) = self.llama_client.context_length().map(|limit| limit as usize) else {
+            return Ok(None);
+        };
+        let tokens = self.count_tokens(message);
+        let system = self.history.first().map_or(0, |msg| self.count_tokens(&msg.content));
+        // Room for the reply, which is also the size of the notes
+        let reserve = self.reply_reserve(limit);
+        if system + tokens + reserve <= limit {
+            return Ok(None);
+        }
+        // Each part shares a request with the notes so far, the instructions and the new notes
+        let part_tokens = limit.saturating_sub(2 * reserve + 200);
+        if part_tokens < 200 {
+            return Err(anyhow::anyhow!(
+                "The message is ~{} tokens and {} only takes {}; too little to read it in parts",
+                tokens, self.current_provider, limit
+            ));
+        }
+        let parts = context::split(message, part_tokens, |text| self.count_tokens(text));
+        println!(
+            "{}",
+            format!("The message is ~{} tokens, more than {} takes ({}); reading it in {} parts", tokens, self.current_provider, limit, parts.len()).dim()
+        );
+
+        let mut notes = String::new();
+        let mut streaming = shutdown::streaming();
+        for (i, part) in parts.iter().enumerate() {
+            print!("\r{}", format!("Reading part {}/{}...", i + 1, parts.len()).dim());
+            stdout().flush()?;
+            let prompt = context::stuffing_prompt(i + 1, parts.len(), &notes, part);
+            let request = [llama::Message { role: "user".to_string(), content: prompt }];
+            notes = tokio::select! {
+                reply = self.llama_client.complete(&request) => reply?,
+                _ = streaming.cancelled() => {
+                    println!();
+                    return Err(anyhow::anyhow!("Cancelled while reading the message"));
+                }
+            };
+        }
+        println!();
+        let last_paragraph = message.trim_end().rsplit("\n\n").next()
+            .filter(|paragraph| self.count_tokens(paragraph) <= reserve);
+        Ok(Some(context::condensed_message(parts.len(), &notes, last_paragraph)))
+    }
+
+    /// `/ask --schema '{...}' <question>`: get the answer as JSON with the schema's fields and
+    /// show a section per field. A reply that doesn't fit the schema is sent back once to fix.
+    async fn ask(&mut self, args: &str) -> Result<()> {
+        let (schema, question) = ask::parse_args(args)?;
+        let prompt = ask::prompt(&schema, &question);
+        let mut client = self.llama_client.clone();
+        client.set_json_mode(true);
+        let (mut messages, _) = self.budgeted_messages();
+        messages.push(llama::Message { role: "user".to_string(), content: prompt.clone() });
+
+        let mut reply = client.complete(&messages).await?;
+        let fields = match ask::fields(&schema, &reply) {
+            Ok(fields) => fields,
+            Err(e) => {
+                println!("{}", format!("{}; asking again", e).dim());
+                messages.push(llama::Message { role: "assistant".to_string(), content: reply });
+                messages.push(llama::Message {
+                    role: "user".to_string(),
+                    content: format!("That doesn't fit: {}. Reply with the corrected JSON object only.", e),
+                });
+                reply = client.complete(&messages).await?;
+                ask::fields(&schema, &reply)?
+            }
+        };
+        Self::create_custom_skin().print_text(&ask::render(&fields));
+
+        // The exchange stays in the conversation so follow-up questions can refer to it
+        self.add_message("user", &prompt);
+        let mut answer = ChatMessage::new("assistant", reply.trim());
+        answer.provider = Some(self.current_provider.clone());
+        answer.model = Some(self.llama_client.model().to_string());
+        self.history.push(answer);
+        self.persist();
+        Ok(())
+    }
+
+    /// Ask for a fresh answer to the last question in place of the current one,
+    /// which is kept if the new request fails.
+    async fn retry(&mut self) -> Result<()> {
+        if self.history.last().is_none_or(|msg| msg.role != "assistant") {
+            println!("No reply to retry yet.");
+            return Ok(());
+        }
+        let previous = self.history.pop().expect("checked above");
+        if let Some(share) = &self.share {
+            share.send("\nAssistant (retried): ".to_string());
+        }
+        if let Err(e) = self.answer().await {
+            self.history.push(previous);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Query with the history as it is, which ends with a question, and add the reply
+    async fn answer(&mut self) -> Result<()> {
+        let result = match self.wait_for_backend().await {
+            Ok(()) => self.query().await,
+            Err(e) => Err(e),
+        };
+        let result = match result {
+            Err(e) if matches!(e.downcast_ref(), Some(llama::LlamaError::QuotaExceeded(_))) => {
+                if self.downgrade(&e)? { self.query().await } else { Err(e) }
+            }
+            Err(e) if self.llama_client.is_local()
+                && matches!(e.downcast_ref(), Some(llama::LlamaError::ServiceUnavailable(_))) =>
+            {
+                self.watchdog.down(&self.current_provider, self.llama_client.clone(), e.to_string());
+                match self.wait_for_backend().await {
+                    Ok(()) => self.query().await,
+                    Err(wait) => Err(wait),
+                }
+            }
+            result => result,
+        };
+        let mut reply = result?;
+        self.record_usage(reply.usage);
+        if self.config.memory.auto_remember {
+            // Asked about before the next prompt; the history keeps the reply without them
+            if let Some((content, facts)) = memory::take_facts(&reply.content) {
+                reply.content = content;
+                self.proposed_facts.extend(facts);
+            }
+        }
+        reply.provider = Some(self.current_provider.clone());
+        reply.model = Some(self.llama_client.model().to_string());
+        reply.temperature = self.llama_client.temperature();
+        self.history.push(reply);
+        self.persist();
+        self.show_provenance();
+        self.find_links();
+        self.show_links();
+        if self.title.is_none() && self.config.default.auto_title {
+            if let Err(e) = self.name_conversation().await {
+                warn!("Could not title the conversation: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // Add a reply's tokens to the running totals and show the cost when the provider has prices
+    fn record_usage(&mut self, usage: Option<llama::Usage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        let prices = self.config.provider_config(&self.current_provider)
+            .map(|p| (p.input_price, p.output_price))
+            .unwrap_or_default();
+        let price = cost::price(&usage, prices.0, prices.1);
+        let model = self.llama_client.model().to_string();
+        self.conversation_cost.add(&self.current_provider, &model, &usage, price);
+        self.session_cost.add(&self.current_provider, &model, &usage, price);
+        if let Some(price) = price {
+            if let Err(e) = self.daily_spend.add(price) {
+                warn!("Failed to record today's spend: {}", e);
+            }
+            let line = format!(
+                "${:.4} this reply, ${:.4} this conversation, ${:.4} this session",
+                price,
+                self.conversation_cost.total(),
+                self.session_cost.total()
+            );
+            println!("{}", line.dim());
+        }
+    }
+
+    fn show_cost(&self) {
+        if self.session_cost.is_empty() {
+            println!("No token usage reported yet.");
+            return;
+        }
+        let skin = Self::create_custom_skin();
+        if !self.conversation_cost.is_empty() {
+            skin.print_text(&format!("**This conversation**\n\n{}", self.conversation_cost.render()));
+        }
+        skin.print_text(&format!("**Since abot started**\n\n{}", self.session_cost.render()));
+        if let Some(limit) = self.config.limits.max_cost_per_day {
+            let spent = self.daily_spend.today();
+            println!("Today: ${:.4} of ${:.2} spent, ${:.4} left", spent, limit, (limit - spent).max(0.0));
+        }
+    }
+
+    /// Check a message against the `[limits]` budgets before it is sent; going over
+    /// needs an explicit yes. Returns whether to send it.
+    fn confirm_budget(&self, rl: &mut DefaultEditor, input: &str) -> Result<bool> {
+        let limits = &self.config.limits;
+        if limits.max_cost_per_day.is_none() && limits.max_tokens_per_request.is_none() {
+            return Ok(true);
+        }
+        let prompt_tokens = self.count_tokens(input)
+            + self.history.iter().map(|msg| self.count_tokens(&msg.content)).sum::<usize>();
+
+        let mut over = Vec::new();
+        if let Some(limit) = limits.max_tokens_per_request.filter(|limit| prompt_tokens > *limit) {
+            over.push(format!("this request is ~{} tokens, the limit is {}", prompt_tokens, limit));
+        }
+        if let Some(limit) = limits.max_cost_per_day {
+            // Assume the reply uses all of max_tokens, so the estimate errs on the high side
+            let provider = self.config.provider_config(&self.current_provider);
+            let usage = llama::Usage {
+                prompt_tokens: prompt_tokens as u32,
+                completion_tokens: self.config.default.max_tokens,
+            };
+            let estimate = provider
+                .and_then(|p| cost::price(&usage, p.input_price, p.output_price))
+                .unwrap_or(0.0);
+            let spent = self.daily_spend.today();
+            if spent + estimate > limit {
+                over.push(format!(
+                    "${:.4} spent today plus up to ${:.4} for this request exceeds the daily limit of ${:.2}",
+                    spent, estimate, limit
+                ));
+            }
+        }
+        if over.is_empty() {
+            return Ok(true);
+        }
+
+        for reason in &over {
+            println!("Over budget: {}", reason);
+        }
+        let answer = rl.readline("Send it anyway? [y/N] ")?;
+        if answer.trim().eq_ignore_ascii_case("y") || answer.trim().eq_ignore_ascii_case("yes") {
+            Ok(true)
+        } else {
+            println!("Not sent");
+            Ok(false)
+        }
+    }
+
+    /// While the local backend is down, hold the message until it answers again;
+    /// Ctrl-C gives up on it.
+    async fn wait_for_backend(&mut self) -> Result<()> {
+        if !self.watchdog.is_down() {
+            return Ok(());
+        }
+        if let Some(banner) = self.watchdog.banner() {
+            println!("{}", banner);
+        }
+        println!("Your message is sent once it is back. Ctrl-C to give up, or /model to switch.");
+        if !self.watchdog.wait().await {
+            return Err(anyhow::anyhow!("Not sent, {} is still down", self.current_provider));
+        }
+        if let Some(banner) = self.watchdog.banner() {
+            println!("{}", banner);
+        }
+        Ok(())
+    }
+
+    /// After a quota error, switch to the provider's fallback for the rest of the session if
+    /// `auto_downgrade` is on, or suggest it. Returns whether the request should be retried.
+    fn downgrade(&mut self, error: &anyhow::Error) -> Result<bool> {
+        let from = self.current_provider.clone();
+        let Some(fallback) = self.config.provider_config(&from).and_then(|p| p.fallback.clone()) else {
+            return Ok(false);
+        };
+        if !self.config.default.auto_downgrade {
+            println!(
+                "{} is out of quota. Type /model {} to continue with its fallback, or set auto_downgrade = true to switch automatically.",
+                from, fallback
+            );
+            return Ok(false);
+        }
+
+        self.set_provider(&fallback)?;
+        let note = format!("Switched from {} to {} ({}) for the rest of the session: {}", from, fallback, self.llama_client.model(), error);
+        println!("{}", note);
+        info!("{}", note);
+        if self.recording {
+            transcript::record_note(&self.transcript_dir()?, &note)?;
+        }
+        Ok(true)
+    }
+
+    /// The query as it goes to the search engine, with private details removed if configured.
+    fn search_query(&self, query: &str) -> String {
+        let settings = &self.config.web_search;
+        if !settings.redact_queries {
+            return query.to_string();
+        }
+        let redacted = redact::search_query(query, &settings.redact_terms, &settings.internal_domains);
+        if !redacted.removed.is_empty() {
+            info!("Redacted {} from the search query, sending '{}'", redacted.removed.join(", "), redacted.text);
+            println!("Removed from the search query: {}", redacted.removed.join(", "));
+        }
+        redacted.text
+    }
+
+    /// Send the current history to the provider and render the reply as it arrives.
+    async fn query(&self) -> Result<ChatMessage> {
+        let started = std::time::Instant::now();
+        let (messages, left_out) = self.budgeted_messages();
+        if left_out > 0 {
+            println!(
+                "{}",
+                format!("Left out the {} oldest messages to stay within ~{} tokens", left_out, self.context_budget().unwrap_or_default()).dim()
+            );
+        }
+        let response = match self.llama_client.generate(&messages).await {
+            Ok(resp) => resp,
+            // A local server that went away is reported by the watchdog instead
+            Err(e) if self.llama_client.is_local()
+                && matches!(e.downcast_ref(), Some(llama::LlamaError::ServiceUnavailable(_))) => return Err(e),
+            Err(e) => {
+                println!("Error generating response: {}", e);
+                return Err(e);
+            }
+        };
+
+        // Error responses are plain JSON even when streaming was requested
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            let message = serde_json::from_str(&body).ok()
+                .and_then(|json| llama::error_message(&json))
+                .unwrap_or(body);
+            if llama::is_quota_error(status, &message) {
+                return Err(llama::LlamaError::QuotaExceeded(message).into());
+            }
+            return Err(llama::LlamaError::RequestFailed(format!("{}: {}", status, message)).into());
+        }
+        
+        if self.llama_client.streams() {
+            // Handle streaming response: one provider call, any number of consumers
+            let mut dispatcher = sink::Dispatcher::new();
+            dispatcher.add(Box::new(sink::Terminal::start(Self::create_custom_skin())?));
+            if let Some(share) = &self.share {
+                dispatcher.add(share.sink());
+            }
+            if self.recording {
+                let stamp = chrono::Local::now().format("%Y%m%d_%H%M%S%.3f");
+                let dir = self.transcript_dir()?;
+                fs::create_dir_all(&dir)?;
+                dispatcher.add(Box::new(sink::TextFile::append(&dir.join(format!("{}_reply.md", stamp)))?));
+            }
+
+            // The engine task decodes the stream; this loop only renders what it reports
+            let (sender, mut receiver) = events::channel();
+            let engine = tokio::spawn(events::stream_reply(self.llama_client.clone(), response, sender));
+            let mut streaming = shutdown::streaming();
+            let mut cancelled = false;
+            loop {
+                let event = tokio::select! {
+                    event = receiver.recv() => event,
+                    _ = streaming.cancelled() => {
+                        engine.abort();
+                        cancelled = true;
+                        None
+                    }
+                };
+                match event {
+                    Some(events::AppEvent::MessageChunk(event)) => dispatcher.dispatch(event)?,
+                    Some(events::AppEvent::Error(message)) => {
+                        println!();
+                        return Err(llama::LlamaError::StreamError(message).into());
+                    }
+                    Some(event) => Self::show_status(&event),
+                    None => break,
+                }
+            }
+
+            // Keep what arrived before a cancel; it can be resumed like a truncated reply
+            let mut message = Self::finish_reply(dispatcher.finish()?);
+            if cancelled {
+                println!("[Cancelled. Type /continue to resume.]");
+                message.truncated = true;
+            }
+            message.elapsed_ms = Some(started.elapsed().as_millis() as u64);
+            Ok(message)
+        } else {
+            // Handle non-streaming response
+            let reply = llama::LlamaClient::get_response(response).await?;
+            println!("Assistant: ");
+            let skin = Self::create_custom_skin();
+            if !reply.thinking.is_empty() {
+                println!("{}", sink::thinking_summary(reply.thinking.split_whitespace().count()).dim());
+            }
+            if self.json_mode {
+                skin.print_text(&Self::json_markdown(&reply.text));
+            } else {
+                skin.print_text(&reply.text);
+            }
+            if let Some(share) = &self.share {
+                share.send(format!("{}\n", reply.text));
+            }
+            println!();
+            let mut message = Self::finish_reply(reply);
+            message.elapsed_ms = Some(started.elapsed().as_millis() as u64);
+            Ok(message)
+        }
+    }
+
+    fn show_status(event: &events::AppEvent) {
+        if let Some(line) = event.status_line() {
+            println!("{}", line);
+        }
+    }
+
+    fn finish_reply(reply: llama::Reply) -> ChatMessage {
+        if let Some(usage) = reply.usage {
+            debug!("Usage: {} prompt + {} completion tokens", usage.prompt_tokens, usage.completion_tokens);
+        }
+        for call in &reply.tool_calls {
+            match call.parse_arguments::<serde_json::Value>() {
+                Ok(arguments) => println!("{}", format!("Tool call: {}({})", call.name, arguments).dim()),
+                Err(e) => println!("{}", e.to_string().dim()),
+            }
+        }
+        let mut message = ChatMessage::new("assistant", &reply.text);
+        message.usage = reply.usage;
+        message.truncated = reply.finish_reason.as_deref().is_some_and(llama::is_truncation);
+        message.thinking = Some(reply.thinking).filter(|thinking| !thinking.is_empty());
+        message.logprobs = reply.logprobs;
+        if message.truncated {
+            println!("[Response truncated at the token limit. Type /continue to keep going.]");
+        }
+        message
+    }
+
+    /// Ask the model to pick up a truncated reply where it stopped and append the rest to it.
+    async fn continue_reply(&mut self) -> Result<()> {
+        if !self.history.last().is_some_and(|msg| msg.role == "assistant" && msg.truncated) {
+            println!("The last reply was not truncated, nothing to continue.");
+            return Ok(());
+        }
+
+        // The instruction is only needed for this request, the stored reply just grows
+        self.add_message("user", "Continue exactly where your previous reply stopped. Do not repeat anything you already wrote.");
+        let result = self.query().await;
+        self.history.pop();
+
+        let continuation = result?;
+        self.record_usage(continuation.usage);
+        let last = self.history.last_mut().expect("checked above");
+        last.content.push_str(&continuation.content);
+        last.truncated = continuation.truncated;
+        if let Some(thinking) = continuation.thinking {
+            last.thinking.get_or_insert_with(String::new).push_str(&thinking);
+        }
+        if let Some(usage) = continuation.usage {
+            last.usage.get_or_insert_with(Default::default).merge(usage);
+        }
+        last.logprobs.extend(continuation.logprobs);
+        self.persist();
+        self.find_links();
+        self.show_links();
+        Ok(())
+    }
+
+    fn save_last_interaction(&self) -> Result<()> {
+        if self.history.len() < 2 {
+            println!("No conversation to save yet.");
+            return Ok(());
+        }
+
+        let save_dir = self.save_dir()?;
+
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+        let filename = save_dir.join(format!("interaction_{}.md", timestamp));
+
+        let last_user_msg = self.history.iter().rev()
+            .find(|msg| msg.role == "user")
             .ok_or_else(|| anyhow::anyhow!("No user message found"))?;
 
         let last_assistant_msg = self.history.iter().rev()
@@ -398,40 +2221,556 @@ impl ChatBot {
             last_assistant_msg.content
         );
 
-        fs::write(&filename, content)?;
-        println!("Saved conversation to: {}", filename.display());
+        files::write_atomic(&filename, content)?;
+        println!("Saved conversation to: {}", filename.display());
+        Ok(())
+    }
+
+    async fn save_digest(&self) -> Result<()> {
+        if self.history.len() < 2 {
+            println!("No conversation to digest yet.");
+            return Ok(());
+        }
+
+        let mut messages = self.request_messages();
+        messages.push(llama::Message {
+            role: "user".to_string(),
+            content: String::from(
+                "Write a structured digest of our whole conversation so far in markdown, \
+                 with exactly these sections:\n\
+                 ## Summary\n## Key decisions\n## Code produced\n## Open questions\n\
+                 Under 'Code produced' list each piece of code with a one-line description \
+                 instead of repeating it. Write 'None' for empty sections."
+            ),
+        });
+
+        println!("Writing digest...");
+        let mut digest = self.llama_client.complete(&messages).await?;
+
+        // Sources come from the injected web results, so list them verbatim
+        let mut seen = std::collections::HashSet::new();
+        let sources: Vec<&str> = self.history.iter()
+            .filter(|msg| msg.web_search)
+            .flat_map(|msg| msg.content.lines())
+            .filter_map(|line| line.strip_prefix("Source: "))
+            .filter(|source| seen.insert(*source))
+            .collect();
+        digest.push_str("\n\n## Sources consulted\n");
+        if sources.is_empty() {
+            digest.push_str("None\n");
+        }
+        for source in sources {
+            digest.push_str(&format!("- {}\n", source));
+        }
+
+        Self::create_custom_skin().print_text(&digest);
+
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+        let filename = self.save_dir()?.join(format!("digest_{}.md", timestamp));
+        files::write_atomic(&filename, digest)?;
+        println!("Saved digest to: {}", filename.display());
+        Ok(())
+    }
+
+    fn save_all_history(&self) -> Result<()> {
+        if self.history.is_empty() {
+            println!("No conversation to save yet.");
+            return Ok(());
+        }
+
+        let save_dir = self.save_dir()?;
+
+        let filename = save_dir.join("saveall.md");
+        let mut content = String::new();
+
+        // Skip the first system message
+        for message in self.history.iter().skip(1) {
+            content.push_str(&format!("{}:{}\n\n", 
+                message.role,
+                message.content
+            ));
+        }
+
+        files::write_atomic(&filename, content)?;
+        println!("Saved full conversation to: {}", filename.display());
+        Ok(())
+    }
+
+    fn save_dir(&self) -> Result<std::path::PathBuf> {
+        let save_dir = dirs::cache_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
+            .join("abot")
+            .join(&self.conversation_id)
+            .join("save");
+        if !save_dir.exists() {
+            fs::create_dir_all(&save_dir)?;
+        }
+        Ok(save_dir)
+    }
+
+    /// Write the conversation as a standalone HTML page in `theme`, to `path` or the save directory.
+    fn export_html(&self, theme: &str, path: Option<&str>) -> Result<()> {
+        // Skip the first system message
+        let messages: Vec<(&str, &str)> = self.history.iter().skip(1)
+            .map(|msg| (msg.role.as_str(), msg.content.as_str()))
+            .collect();
+        if messages.is_empty() {
+            println!("No conversation to export yet.");
+            return Ok(());
+        }
+
+        let title = self.snapshot().topic();
+        let exported = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
+        let filename = match path {
+            Some(path) => std::path::PathBuf::from(path),
+            None => self.save_dir()?.join(format!("conversation_{}.html", chrono::Local::now().format("%Y%m%d_%H%M%S"))),
+        };
+        files::write_atomic(&filename, html::page(theme, &title, &exported, &messages)?)?;
+        println!("Exported conversation to: {}", filename.display());
+        Ok(())
+    }
+
+    /// Write the conversation as a PDF, to `path` or the save directory.
+    fn export_pdf(&self, path: Option<&str>) -> Result<()> {
+        // Skip the first system message
+        let messages: Vec<(&str, &str)> = self.history.iter().skip(1)
+            .map(|msg| (msg.role.as_str(), msg.content.as_str()))
+            .collect();
+        if messages.is_empty() {
+            println!("No conversation to export yet.");
+            return Ok(());
+        }
+
+        let title = self.snapshot().topic();
+        let exported = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
+        let filename = match path {
+            Some(path) => std::path::PathBuf::from(path),
+            None => self.save_dir()?.join(format!("conversation_{}.pdf", chrono::Local::now().format("%Y%m%d_%H%M%S"))),
+        };
+        files::write_atomic(&filename, pdf::document(&title, &exported, &messages)?)?;
+        println!("Exported conversation to: {}", filename.display());
+        Ok(())
+    }
+
+    /// Write the conversation as an OpenAI-style `[{role, content}]` array, the system prompt included.
+    fn export_json(&self, path: Option<&str>) -> Result<()> {
+        if self.history.len() < 2 {
+            println!("No conversation to export yet.");
+            return Ok(());
+        }
+        let filename = match path {
+            Some(path) => std::path::PathBuf::from(path),
+            None => self.save_dir()?.join(format!("conversation_{}.json", chrono::Local::now().format("%Y%m%d_%H%M%S"))),
+        };
+        files::write_atomic(&filename, serde_json::to_string_pretty(&self.request_messages())?)?;
+        println!("Exported conversation to: {}", filename.display());
+        Ok(())
+    }
+
+    /// Start a new conversation from an OpenAI-style `[{role, content}]` array. The configured
+    /// system prompt is kept in place of any system messages in the file.
+    fn import_json(&mut self, path: &str) -> Result<()> {
+        let json = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?;
+        let messages: Vec<llama::Message> = serde_json::from_str(&json)
+            .map_err(|e| anyhow::anyhow!("{} is not a [{{role, content}}] array: {}", path, e))?;
+        if let Some(msg) = messages.iter().find(|msg| !matches!(msg.role.as_str(), "system" | "user" | "assistant")) {
+            return Err(anyhow::anyhow!("Unsupported role '{}' in {}", msg.role, path));
+        }
+
+        self.conversation_id = Uuid::new_v4().to_string();
+        self.claim_conversation();
+        self.title = None;
+        self.conversation_cost = cost::Ledger::default();
+        self.apply_client_settings()?;
+        self.history.truncate(1);
+        self.history.extend(messages.iter()
+            .filter(|msg| msg.role != "system")
+            .map(|msg| ChatMessage::new(&msg.role, &msg.content)));
+        self.persist();
+        println!("Imported {} messages from {}", self.history.len() - 1, path);
+        Ok(())
+    }
+
+    async fn export_audio(&self, last_only: bool) -> Result<()> {
+        let tts = &self.config.tts;
+        if tts.api_key.as_deref().is_some_and(|key| key.contains("your-")) {
+            return Err(anyhow::anyhow!("Please set your TTS API key in the [tts] section of the config file"));
+        }
+
+        let markdown = if last_only {
+            self.history.iter().rev()
+                .find(|msg| msg.role == "assistant")
+                .map(|msg| msg.content.clone())
+                .ok_or_else(|| anyhow::anyhow!("No assistant message found"))?
+        } else {
+            // Skip the first system message
+            self.history.iter().skip(1)
+                .map(|msg| format!("{} said:\n\n{}", msg.role, msg.content))
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        };
+        if markdown.trim().is_empty() {
+            println!("No conversation to export yet.");
+            return Ok(());
+        }
+
+        let chunks = tts::split_input(&tts::speakable_text(&markdown));
+        let client = pool::shared_client();
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+        let save_dir = self.save_dir()?;
+
+        // mp3 frames can simply be concatenated; other containers get one file per chunk
+        let mut audio = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            println!("Synthesizing audio {}/{}...", i + 1, chunks.len());
+            let bytes = tts::synthesize(&client, tts, chunk).await?;
+            if tts.format == "mp3" || chunks.len() == 1 {
+                audio.extend(bytes);
+            } else {
+                let filename = save_dir.join(format!("audio_{}_part{}.{}", timestamp, i + 1, tts.format));
+                files::write_atomic(&filename, bytes)?;
+                println!("Saved audio to: {}", filename.display());
+            }
+        }
+
+        if !audio.is_empty() {
+            let filename = save_dir.join(format!("audio_{}.{}", timestamp, tts.format));
+            files::write_atomic(&filename, audio)?;
+            println!("Saved audio to: {}", filename.display());
+        }
+        Ok(())
+    }
+
+    /// Translate the last reply and show it below the original. Code blocks are kept as they are.
+    async fn translate(&self, language: &str) -> Result<()> {
+        let Some(last) = self.history.iter().rev().find(|msg| msg.role == "assistant") else {
+            println!("No reply to translate yet.");
+            return Ok(());
+        };
+        let provider = self.config.default.translation_provider.as_deref().unwrap_or(&self.current_provider);
+        let client = self.client_for(provider)?;
+        let masked = translate::mask_code(&last.content);
+        let messages = [llama::Message {
+            role: "user".to_string(),
+            content: masked.prompt(language),
+        }];
+
+        println!("Translating into {}...", language);
+        let translation = masked.unmask(&client.complete(&messages).await?);
+        Self::create_custom_skin().print_text(&format!("**Translation ({})**\n\n{}", language, translation));
+        Ok(())
+    }
+
+    /// Probe the given providers (the current one and the web search summarizer by
+    /// default) without generating anything.
+    async fn check_health(&self, providers: &[&str]) -> Result<()> {
+        let mut checks = Vec::new();
+        if providers.is_empty() {
+            checks.push((self.current_provider.clone(), self.llama_client.clone()));
+            if self.current_provider != "llamacpp" {
+                checks.push(("llamacpp (web search summaries)".to_string(), self.client_for("llamacpp")?));
+            }
+        } else {
+            for name in providers {
+                checks.push((name.to_string(), self.client_for(name)?));
+            }
+        }
+        let results = futures::future::join_all(checks.iter().map(|(_, client)| client.health())).await;
+        for ((name, client), health) in checks.iter().zip(results) {
+            let state = if health.available { "up".green() } else { "down".red() };
+            println!(
+                "{} ({}): {} in {}ms, {}",
+                name,
+                client.model(),
+                state,
+                health.latency.as_millis(),
+                health.detail
+            );
+        }
+        Ok(())
+    }
+
+    // The current provider keeps whatever model was picked with /models
+    fn client_for(&self, provider: &str) -> Result<llama::LlamaClient> {
+        if provider == self.current_provider {
+            return Ok(self.llama_client.clone());
+        }
+        let config = self.config.provider_config(provider)
+            .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", provider))?;
+        llama::LlamaClient::new(config.clone().with_sampling_defaults(&self.config.default))
+    }
+
+    /// Send one prompt, in the context of the conversation so far, to several providers at
+    /// once and show the answers side by side. Nothing is added to the history.
+    async fn compare(&self, args: &str) -> Result<()> {
+        let usage = "Usage: /compare --providers ollama,openai <prompt>";
+        let mut words = args.split_whitespace().peekable();
+        let mut providers = Vec::new();
+        let mut prompt = Vec::new();
+        while let Some(word) = words.next() {
+            match (word, words.peek()) {
+                ("--providers", Some(list)) => {
+                    providers = list.split(',').map(String::from).collect();
+                    words.next();
+                }
+                _ => prompt.push(word),
+            }
+        }
+        if providers.len() < 2 || prompt.is_empty() {
+            return Err(anyhow::anyhow!(usage));
+        }
+
+        let clients = providers.iter()
+            .map(|name| Ok((name.clone(), self.client_for(name)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut messages = self.request_messages();
+        messages.push(llama::Message {
+            role: "user".to_string(),
+            content: prompt.join(" "),
+        });
+
+        println!("Asking {}...", providers.join(", "));
+        let answers = compare::ask_all(clients, &messages).await;
+        Self::create_custom_skin().print_text(&compare::render(&answers));
+        Ok(())
+    }
+
+    /// `/bench [--providers a,b] [--runs n] [prompt]`: time the same prompt on each provider.
+    async fn bench(&self, args: &str) -> Result<()> {
+        let mut providers = vec![self.current_provider.clone()];
+        let mut runs = bench::DEFAULT_RUNS;
+        let mut words = args.split_whitespace().peekable();
+        let mut prompt = Vec::new();
+        while let Some(word) = words.next() {
+            match (word, words.peek()) {
+                ("--providers", Some(list)) => {
+                    providers = list.split(',').map(String::from).collect();
+                    words.next();
+                }
+                ("--runs", Some(n)) => {
+                    runs = n.parse().map_err(|_| anyhow::anyhow!("--runs expects a number"))?;
+                    words.next();
+                }
+                _ => prompt.push(word),
+            }
+        }
+        let prompt = if prompt.is_empty() { bench::DEFAULT_PROMPT.to_string() } else { prompt.join(" ") };
+
+        let mut rows = Vec::new();
+        for name in &providers {
+            let client = self.client_for(name)?;
+
+            let mut row = bench::Row {
+                provider: name.clone(),
+                model: client.model().to_string(),
+                samples: Vec::new(),
+                failures: 0,
+            };
+            for run in 1..=runs {
+                print!("\r{}: run {}/{}", name, run, runs);
+                stdout().flush()?;
+                match bench::run_once(&client, &prompt).await {
+                    Ok(sample) => row.samples.push(sample),
+                    Err(e) => {
+                        println!("\r{}: run {} failed: {}", name, run, e);
+                        row.failures += 1;
+                    }
+                }
+            }
+            println!();
+            rows.push(row);
+        }
+
+        Self::create_custom_skin().print_text(&bench::render_table(&rows));
+        Ok(())
+    }
+
+    /// Find the largest prompt the current provider accepts and record it in its config section.
+    async fn probe_context(&mut self, upper: usize) -> Result<()> {
+        let provider = self.current_provider.clone();
+        let mut model_config = self.config.provider_config(&provider)
+            .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", provider))?
+            .clone();
+        model_config.model = self.llama_client.model().to_string();
+        if model_config.api_type == llama::ApiKind::Ollama {
+            println!("Note: Ollama truncates long prompts to num_ctx instead of rejecting them, so expect the upper bound");
+        }
+
+        println!("Probing {} ({}) with up to {} tokens...", provider, model_config.model, upper);
+        let limit = probe::max_context(&model_config, upper).await?;
+        if limit == 0 {
+            return Err(anyhow::anyhow!("Even the smallest probe was rejected, check the provider settings"));
+        }
+        println!("{} accepts prompts of about {} tokens", provider, limit);
+
+        if let Some(section) = self.config.provider_config_mut(&provider) {
+            section.context_length = Some(limit as u32);
+            let path = self.config.save()?;
+            println!("Saved context_length = {} for {} to {}", limit, provider, path.display());
+        }
         Ok(())
     }
 
-    fn save_all_history(&self) -> Result<()> {
-        if self.history.is_empty() {
-            println!("No conversation to save yet.");
+    async fn list_models(&mut self) -> Result<()> {
+        self.listed_models = self.llama_client.list_models().await?;
+        if self.listed_models.is_empty() {
+            println!("{} reported no models", self.current_provider);
             return Ok(());
         }
+        println!("Models available from {}:", self.current_provider);
+        for (i, model) in self.listed_models.iter().enumerate() {
+            let marker = if model == self.llama_client.model() { "*" } else { " " };
+            println!("{} {:>3}. {}", marker, i + 1, model);
+        }
+        println!("Switch with /models <number|name>");
+        Ok(())
+    }
 
-        let cache_dir = dirs::cache_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
-            .join("abot")
-            .join(&self.conversation_id);
+    async fn pull_model(&self, model: &str) -> Result<()> {
+        let response = self.llama_client.pull_model(model).await?;
+        let mut stream = response.bytes_stream();
+        let mut pending = String::new();
+        let mut last_status = String::new();
+        let mut last_was_progress = false;
 
-        let save_dir = cache_dir.join("save");
-        if !save_dir.exists() {
-            fs::create_dir_all(&save_dir)?;
+        while let Some(chunk) = stream.next().await {
+            pending.push_str(&String::from_utf8_lossy(&chunk?));
+            // Progress objects may be split across network chunks
+            while let Some(newline) = pending.find('\n') {
+                let line: String = pending.drain(..=newline).collect();
+                let Ok(progress) = serde_json::from_str::<serde_json::Value>(line.trim()) else {
+                    continue;
+                };
+                if let Some(error) = progress["error"].as_str() {
+                    println!();
+                    return Err(anyhow::anyhow!("Pull failed: {}", error));
+                }
+                let status = progress["status"].as_str().unwrap_or_default();
+                let sizes = match (progress["total"].as_u64(), progress["completed"].as_u64()) {
+                    (Some(total), Some(completed)) if total > 0 => Some((total, completed.min(total))),
+                    _ => None,
+                };
+                // Keep each finished progress bar on its own line
+                if status != last_status && last_was_progress {
+                    println!();
+                }
+                if let Some((total, completed)) = sizes {
+                    let width = 30;
+                    let filled = (completed * width / total) as usize;
+                    print!(
+                        "\r{} [{}{}] {:>3}% {:.1}/{:.1} MB",
+                        status,
+                        "#".repeat(filled),
+                        " ".repeat(width as usize - filled),
+                        completed * 100 / total,
+                        completed as f64 / 1_048_576.0,
+                        total as f64 / 1_048_576.0,
+                    );
+                    stdout().flush()?;
+                } else if status != last_status {
+                    println!("{}", status);
+                }
+                last_was_progress = sizes.is_some();
+                last_status = status.to_string();
+            }
+        }
+        if last_was_progress {
+            println!();
         }
+        println!("Pulled {}", model);
+        Ok(())
+    }
 
-        let filename = save_dir.join("saveall.md");
-        let mut content = String::new();
+    fn select_model(&mut self, choice: &str) {
+        let model = match choice.parse::<usize>() {
+            Ok(n) => match self.listed_models.get(n.wrapping_sub(1)) {
+                Some(model) => model.clone(),
+                None => {
+                    println!("No model #{} in the last listing, run /models first", n);
+                    return;
+                }
+            },
+            Err(_) => choice.to_string(),
+        };
+        self.llama_client.set_model(&model);
+        println!("Using model: {}", model);
+    }
 
-        // Skip the first system message
-        for message in self.history.iter().skip(1) {
-            content.push_str(&format!("{}:{}\n\n", 
-                message.role,
-                message.content
-            ));
+    /// Walk through the config fields (optionally only those under `section`), edit them in
+    /// place and save the result once it validates.
+    fn edit_config(&mut self, rl: &mut DefaultEditor, section: &str) -> Result<()> {
+        let mut doc = toml::Value::try_from(&self.config)?;
+        let mut changed = false;
+
+        loop {
+            let fields: Vec<_> = settings::flatten(&doc)
+                .into_iter()
+                .filter(|(key, _)| section.is_empty() || key.starts_with(section))
+                .collect();
+            for (i, (key, value)) in fields.iter().enumerate() {
+                println!("{:>3}. {} = {}", i + 1, key, settings::display(key, value));
+            }
+
+            let choice = rl.readline("Field number or key = value to edit (Enter when done): ")?;
+            let choice = choice.trim();
+            if choice.is_empty() {
+                break;
+            }
+            let (key, raw) = if let Ok(n) = choice.parse::<usize>() {
+                let Some((key, value)) = n.checked_sub(1).and_then(|i| fields.get(i)) else {
+                    println!("No field #{}", n);
+                    continue;
+                };
+                let raw = rl.readline_with_initial(&format!("{} = ", key), (&settings::editable(value), ""))?;
+                (key.clone(), raw)
+            } else if let Some((key, raw)) = choice.split_once('=') {
+                (key.trim().to_string(), raw.to_string())
+            } else {
+                println!("Enter a field number or key = value");
+                continue;
+            };
+
+            if let Err(e) = settings::set(&mut doc, &key, &raw) {
+                println!("Error: {}", e);
+                continue;
+            }
+            changed = true;
         }
 
-        fs::write(&filename, content)?;
-        println!("Saved full conversation to: {}", filename.display());
+        if !changed {
+            return Ok(());
+        }
+        let config: Config = doc.try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid config, nothing saved: {}", e))?;
+        config.validate()
+            .map_err(|e| anyhow::anyhow!("{}, nothing saved", e))?;
+
+        let answer = rl.readline("Save changes to config.toml? [Y/n] ")?;
+        if answer.trim().eq_ignore_ascii_case("n") {
+            println!("Changes discarded");
+            return Ok(());
+        }
+        let path = config.save()?;
+        println!("Saved to {}", path.display());
+
+        // Rebuild the client so provider changes apply right away
+        match llama::LlamaClient::set_provider(&config, &self.current_provider) {
+            Ok(client) => {
+                self.llama_client = client;
+                self.recording |= config.default.record_transcripts;
+                self.apply_client_settings()?;
+            }
+            Err(e) => println!("Keeping the current client: {}", e),
+        }
+        let web_search = &self.config.web_search;
+        if config.web_search.result_limit != web_search.result_limit
+            || config.web_search.max_page_tokens != web_search.max_page_tokens
+        {
+            println!("Web search settings take effect after a restart");
+        }
+        self.config = config;
+        self.refresh_environment();
+        self.refresh_system_prompt();
         Ok(())
     }
 
@@ -440,9 +2779,235 @@ impl ChatBot {
         if self.current_provider != provider {
             self.llama_client = llama::LlamaClient::set_provider(&self.config, provider)?;
             self.current_provider = provider.to_string();
+            self.watchdog.stop();
+            self.apply_client_settings()?;
+        }
+        Ok(())
+    }
+
+    /// Store a provider's API key in the system keyring and point its config at it.
+    fn set_key(&mut self, provider: &str) -> Result<()> {
+        if self.config.provider_config(provider).is_none() {
+            return Err(anyhow::anyhow!("Unknown provider: {}", provider));
+        }
+        let key = rpassword::prompt_password(format!("API key for {} (input hidden): ", provider))?;
+        let key = key.trim();
+        if key.is_empty() {
+            println!("No key entered, nothing changed");
+            return Ok(());
+        }
+        secrets::store(provider, key)?;
+
+        let reference = format!("{}{}", secrets::KEYRING_PREFIX, provider);
+        let section = self.config.provider_config_mut(provider).expect("checked above");
+        if section.api_key.as_deref() != Some(reference.as_str()) {
+            section.api_key = Some(reference.clone());
+            let path = self.config.save()?;
+            println!("Set api_key = \"{}\" in {}", reference, path.display());
+        }
+        println!("Key for {} stored in the system keyring", provider);
+
+        // The running client holds the old key
+        if provider == self.current_provider {
+            let model = self.llama_client.model().to_string();
+            self.llama_client = llama::LlamaClient::set_provider(&self.config, provider)?;
+            self.llama_client.set_model(&model);
+            self.apply_client_settings()?;
         }
         Ok(())
     }
+
+    fn set_recording(&mut self, on: bool) -> Result<()> {
+        self.recording = on;
+        self.apply_client_settings()?;
+        if on {
+            println!("Recording requests and responses to {}", self.transcript_dir()?.display());
+        } else {
+            println!("Recording stopped");
+        }
+        Ok(())
+    }
+
+    // Clients are rebuilt on provider switches, so these settings live here and are reapplied
+    fn apply_client_settings(&mut self) -> Result<()> {
+        let dir = if self.recording { Some(self.transcript_dir()?) } else { None };
+        self.llama_client.set_transcript_dir(dir);
+        self.llama_client.set_json_mode(self.json_mode);
+        self.llama_client.set_logprobs(self.logprobs);
+        self.web_search.set_conversation(&self.conversation_id)?;
+        // Overrides outlive /model switches, which start from the new provider's config
+        if let Some(temperature) = self.overrides.temperature {
+            self.llama_client.set_temperature(Some(temperature));
+        }
+        if let Some(max_tokens) = self.overrides.max_tokens {
+            self.llama_client.set_max_tokens(Some(max_tokens));
+        }
+        if let Some(stream) = self.overrides.stream {
+            self.llama_client.set_stream(Some(stream));
+        }
+        Ok(())
+    }
+
+    /// `/temp`, `/maxtokens` and `/stream`: change a generation setting for this session,
+    /// or with `reset` go back to the provider's config.
+    fn set_generation(&mut self, setting: &str, value: &str) -> Result<()> {
+        let configured = self.config.provider_config(&self.current_provider).cloned().unwrap_or_default();
+        let reset = value == "reset";
+        match setting {
+            "temp" => {
+                self.overrides.temperature = match value.parse::<f32>() {
+                    _ if reset => None,
+                    Ok(temperature) if (0.0..=2.0).contains(&temperature) => Some(temperature),
+                    _ => return Err(anyhow::anyhow!("The temperature must be a number between 0 and 2")),
+                };
+                self.llama_client.set_temperature(self.overrides.temperature.or(configured.temperature));
+            }
+            "maxtokens" => {
+                self.overrides.max_tokens = match value.parse::<u32>() {
+                    _ if reset => None,
+                    Ok(max_tokens) if max_tokens > 0 => Some(max_tokens),
+                    _ => return Err(anyhow::anyhow!("The token limit must be a positive number")),
+                };
+                self.llama_client.set_max_tokens(self.overrides.max_tokens.or(configured.max_tokens));
+            }
+            "stream" => {
+                self.overrides.stream = match value {
+                    _ if reset => None,
+                    "on" => Some(true),
+                    "off" => Some(false),
+                    _ => return Err(anyhow::anyhow!("Usage: /stream on|off|reset")),
+                };
+                self.llama_client.set_stream(self.overrides.stream);
+            }
+            _ => return Err(anyhow::anyhow!("Unknown setting: {}", setting)),
+        }
+        if reset {
+            println!("Back to the configured value for {}", self.current_provider);
+        } else {
+            println!("Changed for this session, also after /model; /{} reset goes back to the config", setting);
+        }
+        Ok(())
+    }
+
+    /// The input prompt, which lists the session overrides so they aren't forgotten.
+    fn prompt(&self) -> String {
+        let mut overrides = Vec::new();
+        if let Some(temperature) = self.overrides.temperature {
+            overrides.push(format!("temp {}", temperature));
+        }
+        if let Some(max_tokens) = self.overrides.max_tokens {
+            overrides.push(format!("max {} tokens", max_tokens));
+        }
+        if let Some(stream) = self.overrides.stream {
+            overrides.push(if stream { "streaming" } else { "no streaming" }.to_string());
+        }
+        match overrides.is_empty() {
+            true => "You: ".to_string(),
+            false => format!("You ({}): ", overrides.join(", ")),
+        }
+    }
+
+    /// Let others watch this session read-only, or stop (`addr` = "off").
+    async fn set_share(&mut self, addr: Option<&str>) -> Result<()> {
+        if addr == Some("off") {
+            match self.share.take() {
+                Some(_) => println!("Stopped sharing"),
+                None => println!("Not sharing"),
+            }
+            return Ok(());
+        }
+        if let Some(share) = &self.share {
+            println!("Already sharing on {}, /share off to stop", share.addr());
+            return Ok(());
+        }
+        let share = share::Share::start(addr.unwrap_or(share::DEFAULT_ADDR)).await?;
+        if !share.addr().ip().is_loopback() {
+            println!("Warning: anyone who can reach {} can read this conversation", share.addr());
+        }
+        println!("Sharing read-only on {}; watch with `nc {} {}`", share.addr(), share.addr().ip(), share.addr().port());
+        self.share = Some(share);
+        Ok(())
+    }
+
+    fn set_json_mode(&mut self, on: bool) -> Result<()> {
+        self.json_mode = on;
+        self.apply_client_settings()?;
+        self.refresh_system_prompt();
+        if !on {
+            println!("JSON mode off");
+        } else if self.llama_client.api_type() == llama::ApiKind::Anthropic {
+            println!("JSON mode on; {} has no JSON output setting, replies are only checked", self.current_provider);
+        } else {
+            println!("JSON mode on: replies must be a single JSON object");
+        }
+        Ok(())
+    }
+
+    fn set_logprobs(&mut self, on: bool) -> Result<()> {
+        self.logprobs = on;
+        self.apply_client_settings()?;
+        let supported = matches!(
+            self.llama_client.api_type(),
+            llama::ApiKind::OpenAI | llama::ApiKind::Azure
+        );
+        if !on {
+            println!("Token probabilities off");
+        } else if supported {
+            println!("Token probabilities on: /inspect shows the low-confidence parts of the last answer");
+        } else {
+            println!("Token probabilities on, but {} doesn't report them", self.current_provider);
+        }
+        Ok(())
+    }
+
+    /// The last answer with its low-confidence tokens highlighted.
+    fn inspect(&self) {
+        let Some(last) = self.history.iter().rev().find(|msg| msg.role == "assistant") else {
+            println!("No answer to inspect yet.");
+            return;
+        };
+        if last.logprobs.is_empty() {
+            if self.logprobs {
+                println!("{} reported no token probabilities for the last answer.", last.provider.as_deref().unwrap_or(&self.current_provider));
+            } else {
+                println!("No token probabilities for the last answer. Turn them on with /logprobs on.");
+            }
+            return;
+        }
+        println!("{}", inspect::render(&last.logprobs));
+    }
+
+    /// Pretty-print a JSON reply, or warn and fall back to the raw text if it doesn't parse.
+    fn json_markdown(text: &str) -> String {
+        match serde_json::from_str::<serde_json::Value>(text.trim()) {
+            Ok(value) => format!(
+                "```json\n{}\n```",
+                serde_json::to_string_pretty(&value).unwrap_or_else(|_| text.to_string())
+            ),
+            Err(e) => {
+                println!("[Reply is not valid JSON: {}]", e);
+                text.to_string()
+            }
+        }
+    }
+
+    // Hold the current conversation so other abot instances leave it alone
+    fn claim_conversation(&mut self) {
+        self.claim = None;
+        match session::conversation_dir(&self.conversation_id).and_then(|dir| instances::claim(&dir)) {
+            Ok(Some(claim)) => self.claim = Some(claim),
+            Ok(None) => warn!("Conversation {} is also open in another abot", self.conversation_id),
+            Err(e) => warn!("Could not claim conversation {}: {}", self.conversation_id, e),
+        }
+    }
+
+    fn transcript_dir(&self) -> Result<std::path::PathBuf> {
+        Ok(dirs::cache_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
+            .join("abot")
+            .join(&self.conversation_id)
+            .join("transcripts"))
+    }
 }
 
 #[tokio::main]
@@ -470,20 +3035,89 @@ async fn main() -> Result<()> {
             )
         })
         .init();
-    let config = Config::load()?;
-    let mut chatbot = ChatBot::new(config).await?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // `abot render <file.md>` prints the lines and spans a reply would be drawn with, to debug the renderer
+    if args.first().is_some_and(|arg| arg == "render") {
+        let path = args.get(1).ok_or_else(|| anyhow::anyhow!("Usage: abot render <file.md>"))?;
+        let markdown = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Could not read {}: {}", path, e))?;
+        print!("{}", render::outline(&ChatBot::create_custom_skin(), &markdown, render::WIDTH));
+        return Ok(());
+    }
+    let safe_mode = args.iter().any(|arg| arg == "--safe");
+    // `abot new [--template <name>]` skips restoring the last session
+    let fresh = args.first().is_some_and(|arg| arg == "new");
+    let template = args.iter()
+        .position(|arg| arg == "--template")
+        .map(|i| args.get(i + 1).cloned().ok_or_else(|| anyhow::anyhow!("Usage: abot new --template <name>")))
+        .transpose()?;
+    let config = if safe_mode { Config::load_safe()? } else { Config::load()? };
+    pool::init(&config.network)?;
+    shutdown::listen();
+    let mut chatbot = ChatBot::new(config, safe_mode, fresh || template.is_some()).await?;
+    if let Some(name) = template {
+        chatbot.new_conversation(Some(&name))?;
+    }
     let mut rl = DefaultEditor::new()?;
 
+    let mut macros = if safe_mode {
+        macros::Macros::default()
+    } else {
+        macros::Macros::load().unwrap_or_else(|e| {
+            println!("Ignoring unreadable macros: {}", e);
+            macros::Macros::default()
+        })
+    };
+    // Lines of a macro being replayed and prompts typed during a reply, handled as if typed now
+    let mut queued: std::collections::VecDeque<String> = std::collections::VecDeque::new();
+    // The last question and its answer while /edit revises it; put back if the edit is abandoned
+    let mut editing: Option<Vec<ChatMessage>> = None;
+    // Typed but not yet entered while the last reply streamed; the next prompt starts with it
+    let mut draft = String::new();
+
     println!("Welcome to the Abot! Type 'quit' or 'exit' to exit.");
     
     loop {
-        let readline = rl.readline("You: ");
+        chatbot.confirm_facts(&mut rl);
+        let replayed = !queued.is_empty();
+        let readline = match queued.pop_front() {
+            Some(line) => {
+                println!("You: {}", line);
+                Ok(line)
+            }
+            None => {
+                if let Some(banner) = chatbot.watchdog.banner() {
+                    println!("{}", banner);
+                }
+                match editing.as_ref().and_then(|removed| removed.first()) {
+                    Some(question) => rl.readline_with_initial(&chatbot.prompt(), (&ChatBot::editable_prompt(question), "")),
+                    None => rl.readline_with_initial(&chatbot.prompt(), (&std::mem::take(&mut draft), "")),
+                }
+            }
+        };
         match readline {
             Ok(line) => {
                 let line = line.trim();
+                if let Some(removed) = editing.take() {
+                    if line.is_empty() || line.starts_with('/') {
+                        chatbot.history.extend(removed);
+                        println!("Edit abandoned, the question and its answer are back");
+                        if line.is_empty() {
+                            continue;
+                        }
+                    }
+                }
                 if line.eq_ignore_ascii_case("quit") || line.eq_ignore_ascii_case("exit") {
                     break;
                 }
+                if !replayed && !line.starts_with("/macro") {
+                    macros.record(line);
+                }
+                if let Some(lines) = macros.expand(line) {
+                    for queued_line in lines.iter().rev() {
+                        queued.push_front(queued_line.clone());
+                    }
+                    continue;
+                }
                 
                 // Handle commands
                 if line.starts_with('/') {
@@ -498,6 +3132,82 @@ async fn main() -> Result<()> {
                                 println!("Error saving conversation: {}", e);
                             }
                         }
+                        "/digest" => {
+                            if let Err(e) = chatbot.save_digest().await {
+                                println!("Error writing digest: {}", e);
+                            }
+                        }
+                        "/export" => {
+                            let mut args = line.split_whitespace().skip(1);
+                            match args.next() {
+                                Some("audio") => {
+                                    let last_only = args.next() == Some("last");
+                                    if let Err(e) = chatbot.export_audio(last_only).await {
+                                        println!("Error exporting audio: {}", e);
+                                    }
+                                }
+                                Some("html") => {
+                                    // An optional theme name comes before the path
+                                    let mut arg = args.next();
+                                    let theme = match arg {
+                                        Some(name) if html::themes().iter().any(|theme| theme == name) => {
+                                            arg = args.next();
+                                            name
+                                        }
+                                        _ => html::DEFAULT_THEME,
+                                    };
+                                    if let Err(e) = chatbot.export_html(theme, arg) {
+                                        println!("Error exporting HTML: {}", e);
+                                    }
+                                }
+                                Some("json") => {
+                                    if let Err(e) = chatbot.export_json(args.next()) {
+                                        println!("Error exporting JSON: {}", e);
+                                    }
+                                }
+                                Some("pdf") => {
+                                    if let Err(e) = chatbot.export_pdf(args.next()) {
+                                        println!("Error exporting PDF: {}", e);
+                                    }
+                                }
+                                _ => println!("Usage: /export audio [last] | /export html [theme] [path] | /export pdf [path] | /export json [path]"),
+                            }
+                        }
+                        "/import" => {
+                            let mut args = line.split_whitespace().skip(1);
+                            match (args.next(), args.next()) {
+                                (Some("json"), Some(path)) => {
+                                    if let Err(e) = chatbot.import_json(path) {
+                                        println!("Error importing: {}", e);
+                                    }
+                                }
+                                _ => println!("Usage: /import json <path>"),
+                            }
+                        }
+                        "/context" => {
+                            let draft = line.trim_start_matches("/context").trim();
+                            chatbot.show_context(draft);
+                        }
+                        "/models" => {
+                            match line.split_whitespace().nth(1) {
+                                Some(choice) => chatbot.select_model(choice),
+                                None => {
+                                    if let Err(e) = chatbot.list_models().await {
+                                        println!("Error listing models: {}", e);
+                                    }
+                                }
+                            }
+                        }
+                        "/pull" => {
+                            match line.split_whitespace().nth(1) {
+                                Some(model) => {
+                                    if let Err(e) = chatbot.pull_model(model).await {
+                                        println!("Error pulling model: {}", e);
+                                    }
+                                }
+                                None => println!("Usage: /pull <model>"),
+                            }
+                        }
                         "/model" => {
                             match line.split_whitespace().nth(1) {
                                 Some(provider) => {
@@ -506,20 +3216,341 @@ async fn main() -> Result<()> {
                                         Err(e) => println!("Error setting provider: {}", e),
                                     }
                                 }
-                                None => println!("Available providers: deepseek, openai, llamacpp, ollama\nCurrent provider: {}", chatbot.current_provider),
+                                None => println!(
+                                    "Available providers: {}\nCurrent provider: {}",
+                                    chatbot.config.provider_names().join(", "),
+                                    chatbot.current_provider
+                                ),
+                            }
+                        }
+                        "/bench" => {
+                            let args = line.trim_start_matches("/bench");
+                            if let Err(e) = chatbot.bench(args).await {
+                                println!("Error running benchmark: {}", e);
+                            }
+                        }
+                        "/status" => {
+                            let providers: Vec<&str> = line.split_whitespace().skip(1).collect();
+                            if let Err(e) = chatbot.check_health(&providers).await {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/translate" => {
+                            let language = line.trim_start_matches("/translate").trim();
+                            if language.is_empty() {
+                                println!("Usage: /translate <language>");
+                            } else if let Err(e) = chatbot.translate(language).await {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/compare" => {
+                            let args = line.trim_start_matches("/compare");
+                            if let Err(e) = chatbot.compare(args).await {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/probe" => {
+                            let upper = line.split_whitespace().nth(1)
+                                .and_then(|n| n.parse().ok())
+                                .unwrap_or(probe::DEFAULT_UPPER_BOUND);
+                            if let Err(e) = chatbot.probe_context(upper).await {
+                                println!("Error probing context length: {}", e);
+                            }
+                        }
+                        "/share" => {
+                            if let Err(e) = chatbot.set_share(line.split_whitespace().nth(1)).await {
+                                println!("Error sharing session: {}", e);
+                            }
+                        }
+                        "/json" => {
+                            let result = match line.split_whitespace().nth(1) {
+                                Some("on") => chatbot.set_json_mode(true),
+                                Some("off") => chatbot.set_json_mode(false),
+                                None => chatbot.set_json_mode(!chatbot.json_mode),
+                                _ => {
+                                    println!("Usage: /json [on|off]");
+                                    Ok(())
+                                }
+                            };
+                            if let Err(e) = result {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/logprobs" => {
+                            let result = match line.split_whitespace().nth(1) {
+                                Some("on") => chatbot.set_logprobs(true),
+                                Some("off") => chatbot.set_logprobs(false),
+                                None => chatbot.set_logprobs(!chatbot.logprobs),
+                                _ => {
+                                    println!("Usage: /logprobs [on|off]");
+                                    Ok(())
+                                }
+                            };
+                            if let Err(e) = result {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/inspect" => chatbot.inspect(),
+                        "/mark" => {
+                            if let Err(e) = chatbot.mark(line.trim_start_matches("/mark")) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/marks" => chatbot.show_marks(),
+                        "/pin" | "/unpin" => {
+                            let mut words = line.split_whitespace();
+                            let pinned = words.next() == Some("/pin");
+                            if let Err(e) = chatbot.pin(words.next(), pinned) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/jump" | "/unmark" => {
+                            let (command, which) = line.split_once(' ').unwrap_or((line, ""));
+                            let result = match (command, which.trim()) {
+                                (_, "") => {
+                                    println!("Usage: {} <number from /marks | name>", command);
+                                    Ok(())
+                                }
+                                ("/jump", which) => chatbot.jump(which),
+                                (_, which) => chatbot.unmark(which),
+                            };
+                            if let Err(e) = result {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/provenance" => {
+                            let on = match line.split_whitespace().nth(1) {
+                                Some("on") => Some(true),
+                                Some("off") => Some(false),
+                                None => Some(!chatbot.show_provenance),
+                                _ => None,
+                            };
+                            match on {
+                                Some(true) => {
+                                    chatbot.show_provenance = true;
+                                    println!("Replies show their provider, model and settings");
+                                    chatbot.show_provenance();
+                                }
+                                Some(false) => {
+                                    chatbot.show_provenance = false;
+                                    println!("Reply provenance off");
+                                }
+                                None => println!("Usage: /provenance [on|off]"),
+                            }
+                        }
+                        "/edit" => match chatbot.take_last_question() {
+                            Some(removed) => editing = Some(removed),
+                            None => println!("No question to edit yet."),
+                        },
+                        "/fork" => {
+                            let name = line.trim_start_matches("/fork").trim();
+                            if let Err(e) = chatbot.fork(Some(name).filter(|name| !name.is_empty())) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/open" => match line.split_whitespace().nth(1) {
+                            Some(number) => {
+                                if let Err(e) = chatbot.open_link(number) {
+                                    println!("Error: {}", e);
+                                }
+                            }
+                            None if chatbot.links.is_empty() => println!("The last reply mentions no files or issues."),
+                            None => chatbot.show_links(),
+                        },
+                        "/record" => {
+                            let result = match line.split_whitespace().nth(1) {
+                                Some("on") => chatbot.set_recording(true),
+                                Some("off") => chatbot.set_recording(false),
+                                _ => {
+                                    println!("Usage: /record on|off (currently {})", if chatbot.recording { "on" } else { "off" });
+                                    Ok(())
+                                }
+                            };
+                            if let Err(e) = result {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/key" => {
+                            let mut args = line.split_whitespace().skip(1);
+                            match (args.next(), args.next()) {
+                                (Some("set"), Some(provider)) => {
+                                    if let Err(e) = chatbot.set_key(provider) {
+                                        println!("Error storing key: {}", e);
+                                    }
+                                }
+                                _ => println!("Usage: /key set <provider>"),
+                            }
+                        }
+                        "/config" => {
+                            let section = line.trim_start_matches("/config").trim();
+                            if let Err(e) = chatbot.edit_config(&mut rl, section) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/temp" | "/maxtokens" | "/stream" => {
+                            let mut words = line.split_whitespace();
+                            let setting = words.next().unwrap_or_default().trim_start_matches('/');
+                            match words.next() {
+                                Some(value) => {
+                                    if let Err(e) = chatbot.set_generation(setting, value) {
+                                        println!("Error: {}", e);
+                                    }
+                                }
+                                None => println!("Usage: /temp <0-2>, /maxtokens <n> or /stream on|off, or reset to undo"),
+                            }
+                        }
+                        "/ask" => {
+                            if let Err(e) = chatbot.ask(line.trim_start_matches("/ask")).await {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/retry" => {
+                            if let Err(e) = chatbot.retry().await {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/continue" => {
+                            if let Err(e) = chatbot.continue_reply().await {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/thinking" => chatbot.show_thinking(),
+                        "/sessions" => {
+                            let matching = line.trim_start_matches("/sessions").trim();
+                            if let Err(e) = chatbot.list_sessions(Some(matching).filter(|m| !m.is_empty())) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/history" => {
+                            let query = line.trim_start_matches("/history").trim();
+                            if query.is_empty() {
+                                println!("Usage: /history <words>");
+                            } else if let Err(e) = chatbot.search_history(query) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/load" => {
+                            let which = line.trim_start_matches("/load").trim();
+                            if which.is_empty() {
+                                println!("Usage: /load <number from /sessions | topic words>");
+                            } else if let Err(e) = chatbot.load_session(which) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/cost" | "/usage" => chatbot.show_cost(),
+                        "/new" => {
+                            if let Err(e) = chatbot.new_conversation(line.split_whitespace().nth(1)) {
+                                println!("Error: {}", e);
+                            }
+                        }
+                        "/remember" => {
+                            let fact = line.trim_start_matches("/remember").trim();
+                            if fact.is_empty() {
+                                println!("Usage: /remember <fact>");
+                            } else if let Err(e) = chatbot.remember(fact) {
+                                println!("Error saving memory: {}", e);
+                            }
+                        }
+                        "/macro" => {
+                            let mut args = line.split_whitespace().skip(1);
+                            match (args.next(), args.next()) {
+                                (Some("record"), Some(name)) => {
+                                    macros.start(name.trim_start_matches('@'));
+                                    println!("Recording @{}; type /macro stop when done", name.trim_start_matches('@'));
+                                }
+                                (Some("stop"), None) => match macros.stop() {
+                                    Ok(Some((name, count))) => println!("Saved @{} with {} lines", name, count),
+                                    Ok(None) => println!("Not recording"),
+                                    Err(e) => println!("Error saving macro: {}", e),
+                                },
+                                (None | Some("list"), None) => {
+                                    for (name, lines) in macros.list() {
+                                        println!("@{}: {}", name, lines.join(" | "));
+                                    }
+                                    if macros.is_recording() {
+                                        println!("(recording)");
+                                    }
+                                }
+                                (Some("delete"), Some(name)) => match macros.remove(name.trim_start_matches('@')) {
+                                    Ok(true) => println!("Deleted @{}", name.trim_start_matches('@')),
+                                    Ok(false) => println!("No macro named {}", name),
+                                    Err(e) => println!("Error: {}", e),
+                                },
+                                _ => println!("Usage: /macro record <name> | stop | list | delete <name>, replay with @<name>"),
+                            }
+                        }
+                        "/memory" => {
+                            let mut args = line.split_whitespace().skip(1);
+                            match (args.next(), args.next().and_then(|n| n.parse().ok())) {
+                                (None | Some("list"), _) => chatbot.list_memory(),
+                                (Some("forget"), Some(number)) => {
+                                    if let Err(e) = chatbot.forget(number) {
+                                        println!("Error: {}", e);
+                                    }
+                                }
+                                _ => println!("Usage: /memory [list | forget <n>]"),
                             }
                         }
-                        _ => println!("Unknown command. Available commands: /save, /saveall, /model"),
+                        _ => println!("Unknown command. Available commands: /save, /saveall, /digest, /export, /import, /context, /model, /models, /pull, /bench, /compare, /translate, /status, /temp, /maxtokens, /stream, /probe, /record, /json, /ask, /logprobs, /inspect, /mark, /marks, /jump, /unmark, /pin, /unpin, /provenance, /open, /share, /key, /retry, /continue, /edit, /thinking, /cost, /usage, /new, /fork, /sessions, /history, /load, /config, /remember, /memory, /macro"),
                     }
                     continue;
                 }
                 
+                match chatbot.confirm_large_input(&mut rl, line) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                }
+                match chatbot.confirm_budget(&mut rl, line) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                }
+                let line = match chatbot.proofread(&mut rl, line).await {
+                    Ok(text) => text,
+                    Err(e) => {
+                        println!("Proofreading failed, sending as typed: {}", e);
+                        line.to_string()
+                    }
+                };
                 println!("Assistant: ");
-                chatbot.send_message(&line).await?;
+                let typeahead = typeahead::start();
+                if let Err(e) = chatbot.send_message(&line).await {
+                    println!("Error: {}", e);
+                }
+                // Prompts typed during the reply are sent next, in order, before asking for more
+                let (pending, unfinished) = typeahead.finish().await;
+                draft.push_str(&unfinished);
+                for pending_line in pending {
+                    if !pending_line.starts_with("/macro") {
+                        macros.record(&pending_line);
+                    }
+                    queued.push_back(pending_line);
+                }
+                if shutdown::requested() {
+                    break;
+                }
+            }
+            Err(_) => {
+                if let Some(removed) = editing.take() {
+                    chatbot.history.extend(removed);
+                }
+                break;
             }
-            Err(_) => break,
         }
     }
 
+    if !safe_mode {
+        if let Err(e) = session::save(&chatbot.snapshot()) {
+            println!("Error saving session: {}", e);
+        }
+    }
+    shutdown::restore_terminal();
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+use crate::files;
+
+/// Marker the model uses to ask for a fact to be remembered.
+pub const REMEMBER_MARKER: &str = "REMEMBER:";
+
+/// Durable facts about the user, kept in a markdown list at
+/// `~/.config/abot/profile.md` so it can also be edited by hand.
+/// A default profile has no file and only lives for the session.
+#[derive(Default)]
+pub struct Profile {
+    path: Option<PathBuf>,
+    facts: Vec<String>,
+}
+
+impl Profile {
+    pub fn load() -> Result<Self> {
+        let path = dirs::home_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
+            .join(".config")
+            .join("abot")
+            .join("profile.md");
+        let facts = if path.exists() {
+            parse(&fs::read_to_string(&path)?)
+        } else {
+            Vec::new()
+        };
+        Ok(Self { path: Some(path), facts })
+    }
+
+    pub fn facts(&self) -> &[String] {
+        &self.facts
+    }
+
+    /// Record a fact; returns false if it was already known.
+    pub fn remember(&mut self, fact: &str) -> Result<bool> {
+        let fact = fact.trim();
+        if fact.is_empty() || self.facts.iter().any(|f| f.eq_ignore_ascii_case(fact)) {
+            return Ok(false);
+        }
+        self.facts.push(fact.to_string());
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Remove the fact at a 1-based position as shown by `/memory list`.
+    pub fn forget(&mut self, number: usize) -> Result<String> {
+        if number == 0 || number > self.facts.len() {
+            return Err(anyhow::anyhow!("No memory #{}", number));
+        }
+        let fact = self.facts.remove(number - 1);
+        self.save()?;
+        Ok(fact)
+    }
+
+    /// Text appended to the system prompt of every conversation.
+    pub fn system_context(&self, auto_remember: bool) -> String {
+        let mut context = String::new();
+        if !self.facts.is_empty() {
+            context.push_str("\n\nKnown facts about the user:\n");
+            for fact in &self.facts {
+                context.push_str(&format!("- {}\n", fact));
+            }
+        }
+        if auto_remember {
+            context.push_str(&format!(
+                "\n\nWhen the user shares a lasting preference or fact about themselves worth remembering \
+                 across conversations, add a separate line to your reply: {} <fact>",
+                REMEMBER_MARKER
+            ));
+        }
+        context
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content: String = self.facts.iter().map(|fact| format!("- {}\n", fact)).collect();
+        files::write_atomic(path, content)?;
+        Ok(())
+    }
+}
+
+fn parse(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.trim().trim_start_matches(['-', '*']).trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Take the facts the model asked to remember out of a reply: the reply without its
+/// marker lines, and the facts. None when it has no marker lines.
+pub fn take_facts(reply: &str) -> Option<(String, Vec<String>)> {
+    let (markers, kept): (Vec<&str>, Vec<&str>) = reply.lines()
+        .partition(|line| line.trim().starts_with(REMEMBER_MARKER));
+    if markers.is_empty() {
+        return None;
+    }
+    let facts = markers.iter()
+        .filter_map(|line| line.trim().strip_prefix(REMEMBER_MARKER))
+        .map(|fact| fact.trim().to_string())
+        .filter(|fact| !fact.is_empty())
+        .collect();
+    Some((kept.join("\n").trim_end().to_string(), facts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profile() {
+        let facts = parse("# About me\n- uses Arch Linux\n\n* prefers Rust 2021, no unwrap\n");
+        assert_eq!(facts, vec!["uses Arch Linux", "prefers Rust 2021, no unwrap"]);
+    }
+
+    #[test]
+    fn test_take_facts() {
+        let reply = "Sure, pacman it is.\n\nREMEMBER: uses Arch Linux\nREMEMBER:   \n";
+        let (content, facts) = take_facts(reply).unwrap();
+        assert_eq!(content, "Sure, pacman it is.");
+        assert_eq!(facts, vec!["uses Arch Linux"]);
+        assert_eq!(take_facts("No markers here"), None);
+    }
+}
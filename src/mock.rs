@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use reqwest::{Body, Response};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use crate::llama::{estimate_tokens, Message};
+use crate::ModelConfig;
+
+// Roughly the pace of a fast hosted model
+const CHUNK_DELAY: Duration = Duration::from_millis(30);
+
+/// Reply used when no fixtures are configured; exercises the markdown renderer.
+fn builtin_reply(prompt: &str) -> String {
+    format!(
+        "## Mock reply\n\nYou said: *{}*\n\nThis answer comes from the built-in **mock** provider, \
+         no network involved.\n\n- markdown lists\n- `inline code`\n\n```rust\nfn main() {{\n    println!(\"hello from mock\");\n}}\n```\n",
+        prompt.trim()
+    )
+}
+
+/// Canned replies from `dir`, in file name order.
+fn fixtures(dir: &Path) -> Result<Vec<String>> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read mock fixtures from {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    paths.iter()
+        .map(|path| fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display())))
+        .collect()
+}
+
+/// Pick the reply for this request: the n-th user turn gets the n-th fixture, cycling.
+fn reply_for(config: &ModelConfig, messages: &[Message]) -> Result<String> {
+    let prompt = messages.iter().rev().find(|m| m.role == "user").map_or("", |m| m.content.as_str());
+    let Some(dir) = &config.fixtures else {
+        return Ok(builtin_reply(prompt));
+    };
+    let replies = fixtures(dir)?;
+    if replies.is_empty() {
+        return Err(anyhow::anyhow!("No mock fixtures in {}", dir.display()));
+    }
+    let turn = messages.iter().filter(|m| m.role == "user").count().max(1) - 1;
+    Ok(replies[turn % replies.len()].clone())
+}
+
+/// Answer like an OpenAI-compatible server would, streaming word by word when asked to.
+pub fn respond(config: &ModelConfig, messages: &[Message], stream: bool) -> Result<Response> {
+    let reply = reply_for(config, messages)?;
+    let usage = json!({
+        "prompt_tokens": messages.iter().map(|m| estimate_tokens(&m.content)).sum::<usize>(),
+        "completion_tokens": estimate_tokens(&reply),
+    });
+
+    let builder = http::Response::builder().status(200);
+    let response = if stream {
+        let mut events: Vec<String> = reply.split_inclusive(' ')
+            .map(|word| json!({"choices": [{"delta": {"content": word}}]}).to_string())
+            .collect();
+        events.push(json!({"choices": [{"delta": {}, "finish_reason": "stop"}], "usage": usage}).to_string());
+        events.push("[DONE]".to_string());
+
+        let body = futures::stream::iter(events).then(|event| async move {
+            tokio::time::sleep(CHUNK_DELAY).await;
+            Ok::<_, std::io::Error>(format!("data: {}\n\n", event))
+        });
+        builder
+            .header("content-type", "text/event-stream")
+            .body(Body::wrap_stream(body))?
+    } else {
+        let body = json!({
+            "choices": [{"message": {"role": "assistant", "content": reply}, "finish_reason": "stop"}],
+            "usage": usage,
+        });
+        builder
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))?
+    };
+    Ok(Response::from(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::{ApiKind, LlamaClient, StreamEvent};
+
+    fn user(content: &str) -> Message {
+        Message { role: "user".to_string(), content: content.to_string() }
+    }
+
+    fn mock_client() -> LlamaClient {
+        LlamaClient::new(ModelConfig {
+            api_type: ApiKind::Mock,
+            model: "mock".to_string(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_complete_without_network() {
+        let reply = mock_client().complete(&[user("hello there")]).await.unwrap();
+        assert!(reply.contains("You said: *hello there*"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_goes_through_the_decoder() {
+        let client = mock_client();
+        let response = client.stream(&[user("hi")]).await.unwrap();
+        let mut decoder = client.stream_decoder();
+        let mut text = String::new();
+        let mut finished = false;
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            for payload in decoder.push(&chunk.unwrap()) {
+                for event in client.parse_stream_data(&payload, decoder.tool_calls()) {
+                    match event {
+                        StreamEvent::Content(content) => text.push_str(&content),
+                        StreamEvent::Finish(reason) => finished = reason == "stop",
+                        _ => {}
+                    }
+                }
+            }
+        }
+        assert!(decoder.is_done() && finished);
+        assert_eq!(text, builtin_reply("hi"));
+    }
+}
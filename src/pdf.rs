@@ -0,0 +1,281 @@
+use anyhow::Result;
+use printpdf::{BuiltinFont, Color, Mm, PdfDocument, Rgb};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const TEXT_SIZE: f32 = 10.0;
+const CODE_SIZE: f32 = 9.0;
+// What fits between the margins: Courier is exactly 0.6em per character,
+// Helvetica averages a little less
+const TEXT_COLUMNS: usize = 88;
+const CODE_COLUMNS: usize = 88;
+const BLACK: (u8, u8, u8) = (0, 0, 0);
+const GRAY: (u8, u8, u8) = (120, 120, 120);
+const BLUE: (u8, u8, u8) = (30, 80, 160);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Font {
+    Regular,
+    Bold,
+    Code,
+}
+
+/// One line of the document: colored runs of text in a single font. No runs is a blank line.
+#[derive(Debug, PartialEq)]
+struct Line {
+    font: Font,
+    runs: Vec<((u8, u8, u8), String)>,
+}
+
+impl Line {
+    fn text(font: Font, color: (u8, u8, u8), text: &str) -> Self {
+        Self { font, runs: vec![(color, text.to_string())] }
+    }
+
+    fn blank() -> Self {
+        Self { font: Font::Regular, runs: Vec::new() }
+    }
+}
+
+/// The conversation as a PDF, using the built-in PDF fonts so nothing needs to be embedded.
+/// Those only cover Latin-1 (WinAnsi); other characters are left out. `messages` are
+/// (role, markdown) pairs.
+pub fn document(title: &str, exported: &str, messages: &[(&str, &str)]) -> Result<Vec<u8>> {
+    let syntaxes = SyntaxSet::load_defaults_newlines();
+    let themes = ThemeSet::load_defaults();
+    let theme = &themes.themes["InspiredGitHub"];
+
+    let mut lines = vec![
+        Line::text(Font::Bold, BLACK, title),
+        Line::text(Font::Regular, GRAY, &format!("Exported from abot on {}", exported)),
+        Line::blank(),
+    ];
+    for (role, content) in messages {
+        let mut chars = role.chars();
+        let role: String = chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default();
+        lines.push(Line::text(Font::Bold, BLUE, &role));
+        lines.extend(markdown(content, &syntaxes, theme));
+        lines.push(Line::blank());
+    }
+    render(title, &lines)
+}
+
+// Lay out markdown as lines of prose and highlighted code. Links keep their
+// target in parentheses so citations survive on paper
+fn markdown(text: &str, syntaxes: &SyntaxSet, theme: &Theme) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut paragraph = String::new();
+    let mut prefix = String::new();
+    let mut depth = 0;
+    let mut heading = false;
+    let mut code: Option<(String, String)> = None;
+
+    for event in Parser::new_ext(text, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading);
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, block)) = &mut code {
+                    block.push_str(&text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                let (lang, block) = code.take().unwrap_or_default();
+                lines.extend(highlight(&lang, &block, syntaxes, theme));
+                lines.push(Line::blank());
+            }
+            Event::Start(Tag::Heading(_)) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading);
+                heading = true;
+            }
+            Event::End(Tag::Heading(_)) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading);
+                heading = false;
+            }
+            Event::Start(Tag::List(_)) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading);
+                depth += 1;
+            }
+            Event::End(Tag::List(_)) => {
+                depth -= 1;
+                if depth == 0 {
+                    lines.push(Line::blank());
+                }
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading);
+                prefix = format!("{}\u{2022} ", "  ".repeat(depth.max(1) - 1));
+            }
+            Event::End(Tag::Item) => flush(&mut lines, &mut paragraph, &mut prefix, heading),
+            Event::End(Tag::Paragraph) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading);
+                if depth == 0 {
+                    lines.push(Line::blank());
+                }
+            }
+            Event::End(Tag::TableCell) => paragraph.push_str(" | "),
+            Event::End(Tag::TableHead) | Event::End(Tag::TableRow) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading)
+            }
+            Event::End(Tag::Table(_)) | Event::End(Tag::BlockQuote) => {
+                flush(&mut lines, &mut paragraph, &mut prefix, heading);
+                lines.push(Line::blank());
+            }
+            Event::End(Tag::Link(_, url, _)) if !paragraph.ends_with(url.as_ref()) => {
+                paragraph.push_str(&format!(" ({})", url));
+            }
+            Event::Text(text) | Event::Code(text) => paragraph.push_str(&text),
+            Event::SoftBreak => paragraph.push(' '),
+            Event::HardBreak => flush(&mut lines, &mut paragraph, &mut prefix, heading),
+            Event::Rule => lines.push(Line::text(Font::Regular, GRAY, &"-".repeat(40))),
+            _ => {}
+        }
+    }
+    flush(&mut lines, &mut paragraph, &mut prefix, heading);
+    lines
+}
+
+// Word-wrap the pending prose; list items hang their continuation lines under the text
+fn flush(lines: &mut Vec<Line>, paragraph: &mut String, prefix: &mut String, bold: bool) {
+    let text = std::mem::take(paragraph);
+    let prefix = std::mem::take(prefix);
+    if text.trim().is_empty() {
+        return;
+    }
+    let font = if bold { Font::Bold } else { Font::Regular };
+    let indent = " ".repeat(prefix.chars().count());
+    let mut line = prefix;
+    let mut empty = true;
+    for word in text.split_whitespace() {
+        if !empty && line.chars().count() + 1 + word.chars().count() > TEXT_COLUMNS {
+            lines.push(Line::text(font, BLACK, &line));
+            line = indent.clone();
+            empty = true;
+        }
+        if !empty {
+            line.push(' ');
+        }
+        line.push_str(word);
+        empty = false;
+    }
+    lines.push(Line::text(font, BLACK, &line));
+}
+
+// Color code the way syntect would on screen; long lines wrap at the margin
+fn highlight(lang: &str, code: &str, syntaxes: &SyntaxSet, theme: &Theme) -> Vec<Line> {
+    let syntax = syntaxes.find_syntax_by_token(lang).unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut lines = Vec::new();
+    for source in LinesWithEndings::from(code) {
+        let regions = highlighter.highlight_line(source, syntaxes).unwrap_or_else(|_| {
+            vec![(Default::default(), source)]
+        });
+        let mut line = Line { font: Font::Code, runs: Vec::new() };
+        let mut width = 0;
+        for (style, text) in regions {
+            let color = (style.foreground.r, style.foreground.g, style.foreground.b);
+            let text = text.trim_end_matches(['\n', '\r']).replace('\t', "    ");
+            for c in text.chars() {
+                if width == CODE_COLUMNS {
+                    lines.push(std::mem::replace(&mut line, Line { font: Font::Code, runs: Vec::new() }));
+                    width = 0;
+                }
+                match line.runs.last_mut().filter(|(last, _)| *last == color) {
+                    Some((_, run)) => run.push(c),
+                    None => line.runs.push((color, c.to_string())),
+                }
+                width += 1;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn render(title: &str, lines: &[Line]) -> Result<Vec<u8>> {
+    let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "text");
+    let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let courier = doc.add_builtin_font(BuiltinFont::Courier)?;
+
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = PAGE_HEIGHT - MARGIN;
+    for line in lines {
+        let (font, size) = match line.font {
+            Font::Regular => (&regular, TEXT_SIZE),
+            Font::Bold => (&bold, TEXT_SIZE),
+            Font::Code => (&courier, CODE_SIZE),
+        };
+        // Points to millimetres, with some leading
+        let height = size * 0.3528 * 1.4;
+        if y - height < MARGIN {
+            let (page, next) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "text");
+            layer = doc.get_page(page).get_layer(next);
+            y = PAGE_HEIGHT - MARGIN;
+        }
+        y -= height;
+        if line.runs.is_empty() {
+            continue;
+        }
+
+        layer.begin_text_section();
+        layer.set_font(font, size);
+        layer.set_text_cursor(Mm(MARGIN), Mm(y));
+        for ((r, g, b), text) in &line.runs {
+            layer.set_fill_color(Color::Rgb(Rgb::new(*r as f32 / 255.0, *g as f32 / 255.0, *b as f32 / 255.0, None)));
+            layer.write_text(text, font);
+        }
+        layer.end_text_section();
+    }
+    Ok(doc.save_to_bytes()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|line| line.runs.iter().map(|(_, text)| text.as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn test_markdown_layout() {
+        let syntaxes = SyntaxSet::load_defaults_newlines();
+        let themes = ThemeSet::load_defaults();
+        let markdown_text = "Use [the docs](https://doc.rust-lang.org):\n\n- one\n- two\n\n```rust\nfn main() {}\n```\n";
+        let lines = markdown(markdown_text, &syntaxes, &themes.themes["InspiredGitHub"]);
+        assert_eq!(text(&lines), [
+            "Use the docs (https://doc.rust-lang.org):",
+            "",
+            "\u{2022} one",
+            "\u{2022} two",
+            "",
+            "fn main() {}",
+            "",
+        ]);
+        let code = lines.iter().find(|line| line.font == Font::Code).unwrap();
+        assert!(code.runs.len() > 1, "code should be highlighted: {:?}", code);
+
+        let long = "word ".repeat(40);
+        let lines = markdown(&long, &syntaxes, &themes.themes["InspiredGitHub"]);
+        assert!(lines.iter().all(|line| text(std::slice::from_ref(line))[0].len() <= TEXT_COLUMNS));
+    }
+
+    #[test]
+    fn test_document_is_a_pdf() {
+        let pdf = document("Test", "2024-05-01 10:00", &[("user", "hi"), ("assistant", "hello")]).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+}
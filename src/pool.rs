@@ -0,0 +1,268 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use log::warn;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::{Body, Certificate, Client, Identity, NoProxy, Proxy, Response};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::NetworkConfig;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static NETWORK: OnceLock<NetworkConfig> = OnceLock::new();
+static DEDICATED: OnceLock<Mutex<HashMap<ClientOptions, Client>>> = OnceLock::new();
+static LIMITS: OnceLock<Mutex<HashMap<String, Arc<Semaphore>>>> = OnceLock::new();
+static WINDOWS: OnceLock<Mutex<HashMap<String, Window>>> = OnceLock::new();
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Endpoint settings that can't be applied per request and need their own client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ClientOptions {
+    /// Overrides the `[network]` proxy
+    pub proxy: Option<String>,
+    /// PEM file or bundle with extra root certificates, e.g. an internal CA
+    pub ca_cert_path: Option<PathBuf>,
+    /// Client certificate and its PKCS#8 key (both PEM) for mutual TLS
+    pub client_cert_path: Option<PathBuf>,
+    pub client_key_path: Option<PathBuf>,
+    /// Skip certificate validation entirely (self-signed lab setups only)
+    pub accept_invalid_certs: bool,
+}
+
+/// Set up the shared client from the `[network]` section; call once at startup before
+/// any request. Without a configured proxy, reqwest picks up `HTTP_PROXY`, `HTTPS_PROXY`
+/// and `NO_PROXY` from the environment on its own.
+pub fn init(network: &NetworkConfig) -> Result<()> {
+    let _ = NETWORK.set(network.clone());
+    let client = build(&ClientOptions::default())?;
+    let _ = CLIENT.set(client);
+    Ok(())
+}
+
+/// The process-wide HTTP client. Clones share one connection pool, so switching
+/// providers or running several requests at once doesn't redo TLS handshakes.
+pub fn shared_client() -> Client {
+    CLIENT.get_or_init(Client::new).clone()
+}
+
+/// The client for an endpoint with special settings, shared by all endpoints using the same ones.
+pub fn client(options: &ClientOptions) -> Result<Client> {
+    if *options == ClientOptions::default() {
+        return Ok(shared_client());
+    }
+    let mut clients = DEDICATED.get_or_init(Default::default).lock().unwrap();
+    if let Some(client) = clients.get(options) {
+        return Ok(client.clone());
+    }
+    let client = build(options)?;
+    clients.insert(options.clone(), client.clone());
+    Ok(client)
+}
+
+fn build(options: &ClientOptions) -> Result<Client> {
+    let network = NETWORK.get();
+    let mut builder = Client::builder();
+
+    if let Some(proxy) = options.proxy.as_ref().or(network.and_then(|n| n.proxy.as_ref())) {
+        // An explicit proxy replaces the environment's, but hosts exempted there stay exempt
+        let no_proxy = network
+            .and_then(|n| n.no_proxy.as_deref())
+            .and_then(NoProxy::from_string)
+            .or_else(NoProxy::from_env);
+        let proxy = Proxy::all(proxy)
+            .map_err(|e| anyhow::anyhow!("Invalid proxy '{}': {}", proxy, e))?
+            .no_proxy(no_proxy);
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(network) = network {
+        for (host, ip) in &network.resolve {
+            let ip: IpAddr = ip.parse()
+                .map_err(|e| anyhow::anyhow!("Invalid address '{}' for {} in [network.resolve]: {}", ip, host, e))?;
+            // reqwest keeps the port from the URL, only the address is overridden
+            builder = builder.resolve(host, SocketAddr::new(ip, 0));
+        }
+        if network.prefer_ipv4 {
+            builder = builder.dns_resolver(Arc::new(PreferIpv4));
+        }
+    }
+
+    if let Some(path) = &options.ca_cert_path {
+        let pem = fs::read(path)
+            .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    match (&options.client_cert_path, &options.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let read = |path: &PathBuf| fs::read(path)
+                .with_context(|| format!("Failed to read {}", path.display()));
+            let identity = Identity::from_pkcs8_pem(&read(cert_path)?, &read(key_path)?)
+                .with_context(|| format!("Invalid client certificate {}", cert_path.display()))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err(anyhow::anyhow!("Mutual TLS needs both client_cert_path and client_key_path")),
+    }
+    if options.accept_invalid_certs {
+        warn!("TLS certificate validation is disabled for this endpoint");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// System DNS with the IPv4 addresses first. A connection tries the family of the first
+/// address and falls back to the other one, so hosts with only IPv6 stay reachable.
+struct PreferIpv4;
+
+impl Resolve for PreferIpv4 {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            Ok(Box::new(ipv4_first(addrs).into_iter()) as Addrs)
+        })
+    }
+}
+
+// Stable, so each family keeps the order the system returned
+fn ipv4_first(mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    addrs.sort_by_key(SocketAddr::is_ipv6);
+    addrs
+}
+
+/// Wait for one of `limit` concurrent request slots for `key` (usually the endpoint URL).
+/// The first caller for a key decides its limit.
+pub async fn acquire(key: &str, limit: usize) -> OwnedSemaphorePermit {
+    let semaphore = LIMITS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(key.to_string())
+        .or_insert_with(|| Arc::new(Semaphore::new(limit.max(1))))
+        .clone();
+    semaphore
+        .acquire_owned()
+        .await
+        .expect("provider semaphores are never closed")
+}
+
+/// Requests sent to one endpoint during the last minute, with their token counts.
+#[derive(Default)]
+struct Window {
+    sent: VecDeque<(Instant, usize)>,
+}
+
+impl Window {
+    /// Record the request and return `None` if it fits the per-minute limits,
+    /// otherwise how long to wait before asking again.
+    fn admit(&mut self, now: Instant, requests_per_minute: Option<u32>, tokens_per_minute: Option<u32>, tokens: usize) -> Option<Duration> {
+        while self.sent.front().is_some_and(|(at, _)| now.duration_since(*at) >= MINUTE) {
+            self.sent.pop_front();
+        }
+        let too_many_requests = requests_per_minute.is_some_and(|limit| self.sent.len() >= limit as usize);
+        let used: usize = self.sent.iter().map(|(_, tokens)| tokens).sum();
+        // A request larger than the whole budget still goes out once the window is empty
+        let too_many_tokens = tokens_per_minute
+            .is_some_and(|limit| !self.sent.is_empty() && used + tokens > limit as usize);
+        if !too_many_requests && !too_many_tokens {
+            self.sent.push_back((now, tokens));
+            return None;
+        }
+        let (oldest, _) = self.sent.front().expect("a full window has entries");
+        Some(MINUTE.saturating_sub(now.duration_since(*oldest)))
+    }
+}
+
+/// Wait until a request of about `tokens` tokens fits the per-minute limits for `key`,
+/// so bursts are spread out here instead of being rejected by the provider.
+pub async fn throttle(key: &str, requests_per_minute: Option<u32>, tokens_per_minute: Option<u32>, tokens: usize) {
+    if requests_per_minute.is_none() && tokens_per_minute.is_none() {
+        return;
+    }
+    loop {
+        let wait = WINDOWS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .admit(Instant::now(), requests_per_minute, tokens_per_minute, tokens);
+        match wait {
+            None => return,
+            Some(wait) => {
+                warn!("Rate limit for {} reached, waiting {:.1}s", key, wait.as_secs_f64());
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+}
+
+/// Tie `permit` to the response body so a streaming reply keeps its slot
+/// until the last chunk has been read (or the response is dropped).
+pub fn hold_until_read(response: Response, permit: OwnedSemaphorePermit) -> Response {
+    inspect_body(response, move |_| {
+        let _slot = &permit;
+    })
+}
+
+/// Rebuild `response` so `inspect` sees every body chunk as the caller reads it.
+pub fn inspect_body<F>(response: Response, mut inspect: F) -> Response
+where
+    F: FnMut(&[u8]) + Send + 'static,
+{
+    let mut builder = http::Response::builder()
+        .status(response.status())
+        .version(response.version());
+    for (name, value) in response.headers() {
+        builder = builder.header(name, value);
+    }
+
+    let body = response.bytes_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            inspect(bytes);
+        }
+        chunk
+    });
+    Response::from(
+        builder
+            .body(Body::wrap_stream(body))
+            .expect("headers copied from a valid response"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_limits() {
+        let start = Instant::now();
+        let mut window = Window::default();
+        assert_eq!(window.admit(start, Some(2), None, 10), None);
+        assert_eq!(window.admit(start + Duration::from_secs(10), Some(2), None, 10), None);
+        assert_eq!(window.admit(start + Duration::from_secs(20), Some(2), None, 10), Some(Duration::from_secs(40)));
+        assert_eq!(window.admit(start + MINUTE, Some(2), None, 10), None);
+
+        let mut window = Window::default();
+        assert_eq!(window.admit(start, None, Some(100), 500), None);
+        assert_eq!(window.admit(start + Duration::from_secs(30), None, Some(100), 1), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_ipv4_first() {
+        let addrs = ["[2001:db8::1]:0", "192.0.2.1:0", "[2001:db8::2]:0", "192.0.2.2:0"]
+            .map(|addr| addr.parse().unwrap());
+        let sorted: Vec<String> = ipv4_first(addrs.to_vec()).iter().map(|addr| addr.to_string()).collect();
+        assert_eq!(sorted, ["192.0.2.1:0", "192.0.2.2:0", "[2001:db8::1]:0", "[2001:db8::2]:0"]);
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use std::io::{stdout, Write};
+use crate::llama::{LlamaClient, Message};
+use crate::ModelConfig;
+
+pub const DEFAULT_UPPER_BOUND: usize = 131_072;
+// Probing to the exact token isn't worth the extra requests
+const MIN_STEP: usize = 256;
+
+/// Roughly `tokens` tokens of filler; " the" is a single token for common tokenizers.
+pub fn padding(tokens: usize) -> String {
+    " the".repeat(tokens)
+}
+
+/// Send `tokens` of padding and report whether the server took it. Connection
+/// problems are errors, not a "no", so they can't shrink the result.
+async fn accepts(client: &LlamaClient, tokens: usize) -> Result<bool> {
+    let messages = [Message {
+        role: "user".to_string(),
+        content: format!("Reply with OK.{}", padding(tokens)),
+    }];
+    let response = client.generate(&messages).await?;
+    let status = response.status();
+    // Drain the body so the connection can be reused for the next probe
+    let _ = response.bytes().await;
+    Ok(status.is_success())
+}
+
+/// Binary search the largest prompt (in tokens) the provider accepts, up to `upper`.
+pub async fn max_context(config: &ModelConfig, upper: usize) -> Result<usize> {
+    // Ask for a single token without streaming or retries, every probe should be quick and final
+    let client = LlamaClient::new(ModelConfig {
+        max_tokens: Some(1),
+        stream: Some(false),
+        max_attempts: Some(1),
+        ..config.clone()
+    })?;
+
+    let report = |tokens: usize, ok: bool| {
+        print!("\r{:>8} tokens: {}   ", tokens, if ok { "accepted" } else { "rejected" });
+        let _ = stdout().flush();
+    };
+
+    if accepts(&client, upper).await? {
+        report(upper, true);
+        println!();
+        return Ok(upper);
+    }
+    let (mut low, mut high) = (0, upper);
+    while high - low > MIN_STEP.max(low / 100) {
+        let mid = low + (high - low) / 2;
+        let ok = accepts(&client, mid).await?;
+        report(mid, ok);
+        if ok { low = mid } else { high = mid }
+    }
+    println!();
+    Ok(low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::estimate_tokens;
+
+    #[test]
+    fn test_padding_matches_estimate() {
+        assert_eq!(estimate_tokens(&padding(1000)), 1000);
+    }
+}
@@ -0,0 +1,81 @@
+use crossterm::style::Stylize;
+
+/// One step of a word-level diff between the draft and its corrected version.
+#[derive(Debug, PartialEq)]
+pub enum Edit<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+pub fn prompt(draft: &str) -> String {
+    format!(
+        "Fix the spelling and grammar of the text below without changing its meaning, tone or \
+         language. Leave code, file names, URLs and words starting with @ or # exactly as they are. \
+         Reply with the corrected text only, or the text unchanged if nothing needs fixing.\n\n{}",
+        draft
+    )
+}
+
+/// Word diff by longest common subsequence; drafts are short enough for the quadratic table.
+pub fn diff<'a>(old: &'a str, new: &'a str) -> Vec<Edit<'a>> {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut edits = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            edits.push(Edit::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            edits.push(Edit::Added(new[j]));
+            j += 1;
+        } else {
+            edits.push(Edit::Removed(old[i]));
+            i += 1;
+        }
+    }
+    edits
+}
+
+/// The diff on one line: removed words struck out in red, added ones in green.
+pub fn render(edits: &[Edit]) -> String {
+    edits.iter()
+        .map(|edit| match edit {
+            Edit::Same(word) => word.to_string(),
+            Edit::Removed(word) => word.red().crossed_out().to_string(),
+            Edit::Added(word) => word.green().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_diff() {
+        let edits = diff("how do i sort an vector in rust", "How do I sort a vector in Rust?");
+        let changed: Vec<_> = edits.iter().filter(|edit| !matches!(edit, Edit::Same(_))).collect();
+        assert_eq!(changed, [
+            &Edit::Added("How"), &Edit::Removed("how"),
+            &Edit::Added("I"), &Edit::Removed("i"),
+            &Edit::Added("a"), &Edit::Removed("an"),
+            &Edit::Added("Rust?"), &Edit::Removed("rust"),
+        ]);
+        assert!(diff("same words", "same  words").iter().all(|edit| matches!(edit, Edit::Same(_))));
+    }
+}
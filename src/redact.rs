@@ -0,0 +1,98 @@
+use std::net::IpAddr;
+
+// Domain suffixes that only resolve inside a private network
+const INTERNAL_SUFFIXES: [&str; 6] = [".internal", ".local", ".lan", ".corp", ".intranet", ".home.arpa"];
+
+/// Text with sensitive words removed, plus what kind of thing each removal was.
+#[derive(Debug, PartialEq)]
+pub struct Redacted {
+    pub text: String,
+    pub removed: Vec<&'static str>,
+}
+
+fn bare(word: &str) -> &str {
+    word.trim_matches(|c: char| !c.is_alphanumeric())
+}
+
+fn is_email(word: &str) -> bool {
+    match word.split_once('@') {
+        Some((user, domain)) => !user.is_empty() && domain.contains('.') && !domain.contains('@'),
+        None => false,
+    }
+}
+
+fn is_internal_host(word: &str, internal_domains: &[String]) -> bool {
+    let word = word.to_lowercase();
+    (word.contains('.') && INTERNAL_SUFFIXES.iter().any(|suffix| word.ends_with(suffix)))
+        || internal_domains.iter().any(|domain| {
+            let domain = domain.trim_start_matches('.').to_lowercase();
+            word == domain || word.ends_with(&format!(".{}", domain))
+        })
+}
+
+/// Strip what shouldn't leave the machine with a web search: email addresses, IP
+/// addresses, internal hostnames and the configured `terms` (names, project names, ...).
+/// Terms match whole words, case-insensitively, and may span several words.
+pub fn search_query(query: &str, terms: &[String], internal_domains: &[String]) -> Redacted {
+    let words: Vec<&str> = query.split_whitespace().collect();
+    let mut keep = vec![true; words.len()];
+    let mut removed = Vec::new();
+
+    for term in terms {
+        let term: Vec<String> = term.split_whitespace().map(str::to_lowercase).collect();
+        if term.is_empty() || term.len() > words.len() {
+            continue;
+        }
+        for start in 0..=words.len() - term.len() {
+            let matches = term.iter()
+                .enumerate()
+                .all(|(i, part)| bare(words[start + i]).to_lowercase() == *part);
+            if matches {
+                keep[start..start + term.len()].fill(false);
+                removed.push("term");
+            }
+        }
+    }
+
+    for (word, keep) in words.iter().zip(keep.iter_mut()).filter(|(_, keep)| **keep) {
+        let word = bare(word);
+        let kind = if is_email(word) {
+            "email"
+        } else if word.parse::<IpAddr>().is_ok() {
+            "IP address"
+        } else if is_internal_host(word, internal_domains) {
+            "hostname"
+        } else {
+            continue;
+        };
+        *keep = false;
+        removed.push(kind);
+    }
+
+    let text = words.iter()
+        .zip(&keep)
+        .filter(|(_, keep)| **keep)
+        .map(|(word, _)| *word)
+        .collect::<Vec<_>>()
+        .join(" ");
+    Redacted { text, removed }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_query_redaction() {
+        let redacted = search_query(
+            "why does jane.doe@example.com get 502 from build.corp.internal and 10.0.0.5, ask Jane Doe",
+            &["jane doe".to_string()],
+            &[],
+        );
+        assert_eq!(redacted.text, "why does get 502 from and ask");
+        assert_eq!(redacted.removed, vec!["term", "email", "hostname", "IP address"]);
+
+        let untouched = search_query("rust 1.80 release notes", &[], &["example.org".to_string()]);
+        assert!(untouched.removed.is_empty());
+    }
+}
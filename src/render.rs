@@ -0,0 +1,130 @@
+use termimad::minimad::Compound;
+use termimad::{CompositeKind, FmtComposite, FmtLine, FmtText, MadSkin, RelativePosition};
+
+/// Width `abot render` and the snapshot tests lay text out at, so neither depends on the terminal
+pub const WIDTH: usize = 80;
+
+/// The lines termimad draws for `markdown` at `width` columns, one per row: the kind of
+/// line, then its spans with their styles. Colors are left out; they are the skin's business.
+///
+/// ```text
+/// h1        bold:"Setup"
+/// item(1)   "run " code:"cargo build"
+/// rule      top [5, 7]
+/// row       "a" | "b"
+/// ```
+pub fn outline(skin: &MadSkin, markdown: &str, width: usize) -> String {
+    let text = FmtText::from(skin, markdown, Some(width));
+    let mut out = String::new();
+    for line in &text.lines {
+        let line = match line {
+            FmtLine::Normal(composite) => format!("{:<10}{}", kind(composite.kind), spans(composite)),
+            FmtLine::TableRow(row) => {
+                let cells: Vec<String> = row.cells.iter().map(spans).collect();
+                format!("{:<10}{}", "row", cells.join(" | "))
+            }
+            FmtLine::TableRule(rule) => {
+                let position = match rule.position {
+                    RelativePosition::Top => "top",
+                    RelativePosition::Other => "middle",
+                    RelativePosition::Bottom => "bottom",
+                };
+                format!("{:<10}{} {:?}", "rule", position, rule.widths)
+            }
+            FmtLine::HorizontalRule => "hr".to_string(),
+        };
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn kind(kind: CompositeKind) -> String {
+    match kind {
+        CompositeKind::Paragraph => "paragraph".to_string(),
+        CompositeKind::Header(level) => format!("h{}", level),
+        CompositeKind::ListItem(depth) => format!("item({})", depth),
+        // The wrapped continuation of a list item
+        CompositeKind::ListItemFollowUp(depth) => format!("item+({})", depth),
+        CompositeKind::Code => "code".to_string(),
+        CompositeKind::Quote => "quote".to_string(),
+    }
+}
+
+// Wrapping splits text into a compound per word; runs of the same style read as one span
+fn spans(composite: &FmtComposite) -> String {
+    let mut spans: Vec<(Vec<&str>, String)> = Vec::new();
+    for compound in &composite.compounds {
+        let styles = styles(compound);
+        match spans.last_mut().filter(|(last, _)| *last == styles) {
+            Some((_, text)) => text.push_str(compound.src),
+            None => spans.push((styles, compound.src.to_string())),
+        }
+    }
+    let spans: Vec<String> = spans.iter()
+        .map(|(styles, text)| match styles.is_empty() {
+            true => format!("{:?}", text),
+            false => format!("{}:{:?}", styles.join("+"), text),
+        })
+        .collect();
+    spans.join(" ")
+}
+
+fn styles(compound: &Compound) -> Vec<&'static str> {
+    [
+        (compound.bold, "bold"),
+        (compound.italic, "italic"),
+        (compound.code, "code"),
+        (compound.strikeout, "strike"),
+    ]
+    .into_iter()
+    .filter_map(|(on, style)| on.then_some(style))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(markdown: &str) -> String {
+        outline(&MadSkin::default(), markdown, WIDTH)
+    }
+
+    #[test]
+    fn test_inline_styles() {
+        let markdown = "# Setup\n\nRun **cargo build** with *care*, not ~~cargo run~~, then `abot`.\n\n> quoted advice\n\n---\n";
+        insta::assert_snapshot!(render(markdown));
+    }
+
+    #[test]
+    fn test_lists() {
+        let markdown = concat!(
+            "Steps:\n\n",
+            "* install the toolchain\n",
+            "* build it\n",
+            "  * in release mode, which takes a while longer but produces a binary that is a lot faster to run\n",
+            "* run `abot`\n",
+        );
+        insta::assert_snapshot!(render(markdown));
+    }
+
+    #[test]
+    fn test_tables() {
+        let markdown = concat!(
+            "| provider | streaming | cost |\n",
+            "|:---|:---:|---:|\n",
+            "| openai | yes | $$ |\n",
+            "| llama.cpp | **yes** | free |\n",
+        );
+        insta::assert_snapshot!(render(markdown));
+    }
+
+    #[test]
+    fn test_code_and_diffs() {
+        let markdown = concat!(
+            "```rust\nfn main() {\n    println!(\"hi\");\n}\n```\n\n",
+            "```diff\n- let x = 1;\n+ let x = 2;\n```\n",
+        );
+        insta::assert_snapshot!(render(markdown));
+    }
+}
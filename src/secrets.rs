@@ -0,0 +1,55 @@
+use anyhow::Result;
+use keyring::Entry;
+
+/// `api_key = "keyring:<name>"` reads the key from the system secret store instead of the file.
+pub const KEYRING_PREFIX: &str = "keyring:";
+/// `api_key = "env:<VAR>"` reads the key from an environment variable at startup.
+pub const ENV_PREFIX: &str = "env:";
+const SERVICE: &str = "abot";
+
+/// The actual secret behind a config value; plain values are returned unchanged.
+pub fn resolve(value: &str) -> Result<String> {
+    if let Some(var) = value.strip_prefix(ENV_PREFIX) {
+        return std::env::var(var).map_err(|_| anyhow::anyhow!("Environment variable {} is not set", var));
+    }
+    let Some(name) = value.strip_prefix(KEYRING_PREFIX) else {
+        return Ok(value.to_string());
+    };
+    Entry::new(SERVICE, name)?
+        .get_password()
+        .map_err(|e| anyhow::anyhow!("No key '{}' in the system keyring ({}), store one with /key set {}", name, e, name))
+}
+
+/// `<PROVIDER>_API_KEY`, e.g. `LM_STUDIO_API_KEY` for a `[providers.lm-studio]` section.
+pub fn env_var(provider: &str) -> String {
+    let name: String = provider.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("{}_API_KEY", name)
+}
+
+/// Whether the keyring holds a key for `name`; an unreachable keyring counts as no.
+pub fn has_key(name: &str) -> bool {
+    Entry::new(SERVICE, name).and_then(|entry| entry.get_password()).is_ok()
+}
+
+pub fn store(name: &str, secret: &str) -> Result<()> {
+    Entry::new(SERVICE, name)?.set_password(secret)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_values_pass_through() {
+        assert_eq!(resolve("sk-plain").unwrap(), "sk-plain");
+    }
+
+    #[test]
+    fn test_env_var_names() {
+        assert_eq!(env_var("deepseek"), "DEEPSEEK_API_KEY");
+        assert_eq!(env_var("lm-studio"), "LM_STUDIO_API_KEY");
+    }
+}
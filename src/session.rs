@@ -0,0 +1,243 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::files;
+use crate::ChatMessage;
+
+// Longest topic shown by /sessions
+const TOPIC_CHARS: usize = 60;
+// Generated titles are asked for in 3-6 words; longer replies are cut
+const TITLE_WORDS: usize = 6;
+// Longest title part of a conversation directory name
+const SLUG_CHARS: usize = 40;
+
+/// Everything needed to pick the last conversation up where it was left:
+/// written on exit, restored on the next start unless `--safe` is given.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub conversation_id: String,
+    pub provider: String,
+    pub model: String,
+    pub recording: bool,
+    /// Template the conversation was started from, see `/new <template>`
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Generated after the first exchange, see `auto_title`
+    #[serde(default)]
+    pub title: Option<String>,
+    pub history: Vec<ChatMessage>,
+}
+
+fn dir() -> Result<PathBuf> {
+    Ok(dirs::cache_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
+        .join("abot"))
+}
+
+fn path() -> Result<PathBuf> {
+    Ok(dir()?.join("session.json"))
+}
+
+/// Where a conversation keeps its messages, web cache and transcripts.
+pub fn conversation_dir(conversation_id: &str) -> Result<PathBuf> {
+    Ok(dir()?.join(conversation_id))
+}
+
+fn conversation_path(conversation_id: &str) -> Result<PathBuf> {
+    Ok(conversation_dir(conversation_id)?.join("conversation.json"))
+}
+
+impl Snapshot {
+    /// What the conversation is about: its title, or the start of its first question.
+    pub fn topic(&self) -> String {
+        if let Some(title) = &self.title {
+            return title.clone();
+        }
+        let Some(first) = self.history.iter().find(|msg| msg.role == "user") else {
+            return "(empty)".to_string();
+        };
+        // Web search prompts quote the question after the injected instructions
+        let question = first.content.split_once("answer the question: '")
+            .filter(|_| first.web_search)
+            .and_then(|(_, rest)| rest.split_once("'\n"))
+            .map_or(first.content.as_str(), |(question, _)| question);
+        let line = question.lines().next().unwrap_or_default().trim();
+        if line.chars().count() > TOPIC_CHARS {
+            format!("{}...", line.chars().take(TOPIC_CHARS).collect::<String>())
+        } else {
+            line.to_string()
+        }
+    }
+
+    pub fn turns(&self) -> usize {
+        self.history.iter().filter(|msg| msg.role == "user").count()
+    }
+
+    /// Whether a question or reply mentions `text`, ignoring case.
+    pub fn mentions(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.history.iter()
+            .filter(|msg| msg.role != "system")
+            .any(|msg| msg.content.to_lowercase().contains(&text))
+    }
+}
+
+/// A saved conversation as listed by `/sessions`.
+pub struct Summary {
+    pub conversation_id: String,
+    pub topic: String,
+    pub turns: usize,
+    pub updated: SystemTime,
+}
+
+/// Store the conversation under its own directory; called after every reply.
+pub fn save_conversation(snapshot: &Snapshot) -> Result<()> {
+    let path = conversation_path(&snapshot.conversation_id)?;
+    let dir = path.parent().ok_or_else(|| anyhow::anyhow!("No directory for {}", path.display()))?;
+    fs::create_dir_all(dir)?;
+    // Another abot may be saving the same conversation
+    let _lock = lock(dir)?;
+    files::write_atomic(&path, serde_json::to_string_pretty(snapshot)?)
+}
+
+/// Take the lock of the conversation stored in `dir`, held by everything that writes
+/// into it: the saved copy, the web cache and the transcripts.
+pub fn lock(dir: &Path) -> Result<File> {
+    fs::create_dir_all(dir)?;
+    files::lock(&dir.join(".lock"))
+}
+
+pub fn load_conversation(conversation_id: &str) -> Result<Snapshot> {
+    let path = conversation_path(conversation_id)?;
+    let json = fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("No saved conversation {}: {}", conversation_id, e))?;
+    serde_json::from_str(&json).map_err(|e| anyhow::anyhow!("Unreadable conversation {}: {}", path.display(), e))
+}
+
+/// Saved conversations with at least one question (that mention `matching`, if given),
+/// most recently updated first.
+pub fn list(matching: Option<&str>) -> Result<Vec<Summary>> {
+    let mut summaries = Vec::new();
+    let Ok(entries) = fs::read_dir(dir()?) else {
+        return Ok(summaries);
+    };
+    for entry in entries.flatten() {
+        let path = entry.path().join("conversation.json");
+        let Ok(json) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let Ok(snapshot) = serde_json::from_str::<Snapshot>(&json) else {
+            continue;
+        };
+        if snapshot.turns() == 0 || matching.is_some_and(|text| !snapshot.mentions(text)) {
+            continue;
+        }
+        summaries.push(Summary {
+            topic: snapshot.topic(),
+            turns: snapshot.turns(),
+            updated: fs::metadata(&path).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH),
+            conversation_id: snapshot.conversation_id,
+        });
+    }
+    summaries.sort_by_key(|s| std::cmp::Reverse(s.updated));
+    Ok(summaries)
+}
+
+/// Move everything kept for a conversation (saved copy, web cache, transcripts)
+/// to the directory of its new id, which must not be taken yet.
+pub fn rename_conversation(from: &str, to: &str) -> Result<()> {
+    let (from, to) = (dir()?.join(from), dir()?.join(to));
+    if to.exists() {
+        return Err(anyhow::anyhow!("{} already exists", to.display()));
+    }
+    if from.exists() {
+        fs::rename(from, to)?;
+    }
+    Ok(())
+}
+
+pub fn title_prompt(question: &str, answer: &str) -> String {
+    let answer: String = answer.chars().take(1000).collect();
+    format!(
+        "Give this conversation a title of 3 to 6 words. Reply with the title only, \
+         no quotes or punctuation at the end.\n\nQuestion: {}\n\nAnswer: {}",
+        question, answer
+    )
+}
+
+/// The model's reply cut down to a title: its first line, without quotes, a `Title:` label
+/// or a final period, at most six words.
+pub fn clean_title(reply: &str) -> Option<String> {
+    let line = reply.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let line = line.strip_prefix("Title:").or_else(|| line.strip_prefix("title:")).unwrap_or(line);
+    let line = line.trim_matches(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '*' | '#' | '.' | '`'));
+    let title = line.split_whitespace().take(TITLE_WORDS).collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+/// A directory-safe id for a titled conversation: the title in lowercase with dashes,
+/// plus the start of the old id since titles repeat.
+pub fn titled_id(title: &str, conversation_id: &str) -> String {
+    let mut slug = String::new();
+    for c in title.to_lowercase().chars() {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.chars().take(SLUG_CHARS).collect();
+    let suffix: String = conversation_id.chars().filter(char::is_ascii_alphanumeric).take(8).collect();
+    format!("{}-{}", slug.trim_end_matches('-'), suffix).trim_start_matches('-').to_string()
+}
+
+pub fn save(snapshot: &Snapshot) -> Result<()> {
+    fs::create_dir_all(dir()?)?;
+    files::write_atomic(&path()?, serde_json::to_string_pretty(snapshot)?)
+}
+
+pub fn load() -> Result<Option<Snapshot>> {
+    let path = path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let snapshot = serde_json::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| anyhow::anyhow!("Ignoring unreadable session {}: {}", path.display(), e))?;
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_from_older_sessions_load() {
+        let json = r#"{"conversation_id": "c", "provider": "mock", "model": "mock", "recording": false,
+            "history": [{"role": "user", "content": "hi"}]}"#;
+        let snapshot: Snapshot = serde_json::from_str(json).unwrap();
+        assert_eq!(snapshot.history[0].content, "hi");
+        assert!(!snapshot.history[0].truncated && snapshot.history[0].thinking.is_none());
+    }
+
+    #[test]
+    fn test_topic_is_the_first_question() {
+        let json = r#"{"conversation_id": "c", "provider": "mock", "model": "mock", "recording": false,
+            "history": [{"role": "system", "content": "Be brief"},
+                        {"role": "user", "content": "Based on the following web search results, please answer the question: 'rust 1.80 news'\n\nSearch Results:\n...", "web_search": true},
+                        {"role": "assistant", "content": "..."}]}"#;
+        let snapshot: Snapshot = serde_json::from_str(json).unwrap();
+        assert_eq!(snapshot.topic(), "rust 1.80 news");
+        assert_eq!(snapshot.turns(), 1);
+    }
+
+    #[test]
+    fn test_generated_titles() {
+        assert_eq!(clean_title("\n\"Sorting Vectors in Rust.\"\n").as_deref(), Some("Sorting Vectors in Rust"));
+        assert_eq!(clean_title("Title: **A very long title that keeps going on**").as_deref(), Some("A very long title that keeps"));
+        assert_eq!(clean_title("  \n"), None);
+        assert_eq!(titled_id("Sorting Vectors in Rust!", "0f8e2c1a-9b7d-4c1e"), "sorting-vectors-in-rust-0f8e2c1a");
+        assert_eq!(titled_id("../..", "0f8e2c1a-9b7d-4c1e"), "0f8e2c1a");
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::Result;
+use toml::Value;
+
+/// Every leaf of a config document as a dotted key (`openai.model`) and its value.
+pub fn flatten(value: &Value) -> Vec<(String, Value)> {
+    fn walk(prefix: &str, value: &Value, out: &mut Vec<(String, Value)>) {
+        match value {
+            Value::Table(table) => {
+                for (key, child) in table {
+                    let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                    walk(&path, child, out);
+                }
+            }
+            leaf => out.push((prefix.to_string(), leaf.clone())),
+        }
+    }
+    let mut out = Vec::new();
+    walk("", value, &mut out);
+    out
+}
+
+/// How a value is shown in the editor; secrets only reveal their last characters.
+pub fn display(key: &str, value: &Value) -> String {
+    match value {
+        Value::String(s) if key.ends_with("api_key") && s.chars().count() > 8 => {
+            let tail: String = s.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+            format!("\"…{}\"", tail)
+        }
+        Value::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+/// The raw text offered for editing: strings without quotes, everything else as TOML.
+pub fn editable(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Set `key` to the user's input. Existing string fields stay strings; anything else is
+/// read as a TOML literal, falling back to a string. Empty input removes the key.
+pub fn set(root: &mut Value, key: &str, raw: &str) -> Result<()> {
+    let mut parts: Vec<&str> = key.split('.').map(str::trim).collect();
+    let leaf = parts.pop().filter(|k| !k.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Invalid key '{}'", key))?;
+
+    let mut table = root.as_table_mut()
+        .ok_or_else(|| anyhow::anyhow!("Config is not a table"))?;
+    for part in parts {
+        table = table.entry(part)
+            .or_insert_with(|| Value::Table(Default::default()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("'{}' is not a section", part))?;
+    }
+
+    let raw = raw.trim();
+    if raw.is_empty() {
+        table.remove(leaf);
+        return Ok(());
+    }
+    let value = match table.get(leaf) {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        _ => parse_literal(raw).unwrap_or_else(|| Value::String(raw.to_string())),
+    };
+    table.insert(leaf.to_string(), value);
+    Ok(())
+}
+
+fn parse_literal(raw: &str) -> Option<Value> {
+    let mut doc: toml::Table = toml::from_str(&format!("v = {}", raw)).ok()?;
+    doc.remove("v")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_keeps_types() {
+        let mut root: Value = toml::from_str("[default]\ntemperature = 0.7\ninitial_prompt = \"hi\"\n").unwrap();
+        set(&mut root, "default.temperature", "0.2").unwrap();
+        set(&mut root, "default.initial_prompt", "42").unwrap();
+        set(&mut root, "openai.max_tokens", "512").unwrap();
+        assert_eq!(root["default"]["temperature"], Value::Float(0.2));
+        assert_eq!(root["default"]["initial_prompt"], Value::String("42".into()));
+        assert_eq!(root["openai"]["max_tokens"], Value::Integer(512));
+
+        set(&mut root, "openai.max_tokens", "").unwrap();
+        assert!(root["openai"].get("max_tokens").is_none());
+        assert!(flatten(&root).iter().any(|(key, _)| key == "default.initial_prompt"));
+    }
+
+    #[test]
+    fn test_display_masks_keys() {
+        let key = Value::String("sk-1234567890abcd".into());
+        assert_eq!(display("openai.api_key", &key), "\"…abcd\"");
+        assert_eq!(display("openai.model", &key), "\"sk-1234567890abcd\"");
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use log::{debug, warn};
+use std::net::SocketAddr;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use crate::llama::StreamEvent;
+use crate::sink::Sink;
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+// Viewers that fall this far behind skip ahead instead of slowing the session down
+const BACKLOG: usize = 1024;
+
+/// A read-only mirror of the session: everyone connected to `addr` sees the prompts and
+/// the replies as they stream. Plain text, so `nc host port` is enough to watch, also
+/// through an SSH port forward. Stops accepting viewers when dropped.
+pub struct Share {
+    addr: SocketAddr,
+    sender: broadcast::Sender<String>,
+    listener: JoinHandle<()>,
+}
+
+impl Share {
+    pub async fn start(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let (sender, _) = broadcast::channel::<String>(BACKLOG);
+        let viewers = sender.clone();
+        let listener = tokio::spawn(async move {
+            loop {
+                let (mut socket, peer) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        warn!("Share listener failed: {}", e);
+                        return;
+                    }
+                };
+                debug!("Viewer attached from {}", peer);
+                let mut updates = viewers.subscribe();
+                tokio::spawn(async move {
+                    let _ = socket.write_all(b"Attached to abot (read-only)\n").await;
+                    loop {
+                        let text = match updates.recv().await {
+                            Ok(text) => text,
+                            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => return,
+                        };
+                        if socket.write_all(text.as_bytes()).await.is_err() {
+                            debug!("Viewer {} left", peer);
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(Self { addr, sender, listener })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send text to every viewer; nobody watching is fine.
+    pub fn send(&self, text: String) {
+        let _ = self.sender.send(text);
+    }
+
+    /// A sink that mirrors the reply being generated.
+    pub fn sink(&self) -> Box<dyn Sink> {
+        Box::new(Mirror { sender: self.sender.clone() })
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        // Connected viewers see the stream end once the last sender is gone
+        self.listener.abort();
+    }
+}
+
+struct Mirror {
+    sender: broadcast::Sender<String>,
+}
+
+impl Sink for Mirror {
+    fn event(&mut self, event: &StreamEvent) -> Result<()> {
+        if let StreamEvent::Content(content) = event {
+            let _ = self.sender.send(content.clone());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let _ = self.sender.send("\n".to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_viewer_sees_the_stream() {
+        let share = Share::start("127.0.0.1:0").await.unwrap();
+        let mut viewer = TcpStream::connect(share.addr()).await.unwrap();
+        let mut greeting = [0u8; 29];
+        viewer.read_exact(&mut greeting).await.unwrap();
+
+        let mut sink = share.sink();
+        sink.event(&StreamEvent::Content("Hello".into())).unwrap();
+        sink.finish().unwrap();
+        let mut text = [0u8; 6];
+        viewer.read_exact(&mut text).await.unwrap();
+        assert_eq!(&text, b"Hello\n");
+    }
+}
@@ -0,0 +1,88 @@
+use crossterm::{cursor, execute};
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tokio::sync::watch;
+
+static STREAMING: AtomicBool = AtomicBool::new(false);
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static CANCEL: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+fn cancel_sender() -> &'static watch::Sender<bool> {
+    CANCEL.get_or_init(|| watch::channel(false).0)
+}
+
+/// Held while a reply streams in, so a signal cancels the stream instead of killing the app.
+pub struct Streaming {
+    cancel: watch::Receiver<bool>,
+}
+
+pub fn streaming() -> Streaming {
+    cancel_sender().send_replace(false);
+    STREAMING.store(true, Ordering::SeqCst);
+    Streaming {
+        cancel: cancel_sender().subscribe(),
+    }
+}
+
+impl Streaming {
+    /// Resolves once Ctrl-C or SIGTERM asked for the stream to stop.
+    pub async fn cancelled(&mut self) {
+        let _ = self.cancel.wait_for(|cancel| *cancel).await;
+    }
+}
+
+impl Drop for Streaming {
+    fn drop(&mut self) {
+        STREAMING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Whether SIGTERM asked the app to exit once the current reply is wrapped up.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Handle Ctrl-C and SIGTERM. While a reply streams, Ctrl-C only cancels it and SIGTERM
+/// cancels it and then ends the main loop. At the prompt nothing is in flight, so exit directly.
+pub fn listen() {
+    tokio::spawn(async {
+        loop {
+            let terminate = wait_for_signal().await;
+            if terminate {
+                REQUESTED.store(true, Ordering::SeqCst);
+            }
+            if STREAMING.load(Ordering::SeqCst) {
+                cancel_sender().send_replace(true);
+            } else {
+                restore_terminal();
+                std::process::exit(if terminate { 143 } else { 130 });
+            }
+        }
+    });
+}
+
+// Returns true for SIGTERM, false for Ctrl-C
+async fn wait_for_signal() -> bool {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => false,
+            _ = term.recv() => true,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        false
+    }
+}
+
+/// Leave the terminal usable and push out buffered log lines.
+pub fn restore_terminal() {
+    let _ = execute!(stdout(), cursor::Show);
+    let _ = stdout().flush();
+    log::logger().flush();
+}
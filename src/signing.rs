@@ -0,0 +1,79 @@
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use sha2::{Digest, Sha256};
+use crate::SigningConfig;
+
+/// Headers carrying the signature; the gateway recomputes it from the same parts.
+pub const TIMESTAMP_HEADER: &str = "x-abot-timestamp";
+pub const NONCE_HEADER: &str = "x-abot-nonce";
+pub const SIGNATURE_HEADER: &str = "x-abot-signature";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// HMAC-SHA256 over `timestamp \n nonce \n METHOD \n path?query \n hex(sha256(body))`.
+pub fn signature(secret: &str, timestamp: i64, nonce: &str, method: &str, path: &str, body: &[u8]) -> Result<String> {
+    let canonical = format!(
+        "{}\n{}\n{}\n{}\n{}",
+        timestamp,
+        nonce,
+        method.to_uppercase(),
+        path,
+        to_hex(&Sha256::digest(body))
+    );
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Invalid signing secret: {}", e))?;
+    mac.update(canonical.as_bytes());
+    Ok(to_hex(&mac.finalize().into_bytes()))
+}
+
+/// Fresh timestamp, nonce and signature headers for one request. `clock_offset` is the
+/// number of seconds the gateway's clock is ahead of ours.
+pub fn sign_request(config: &SigningConfig, method: &str, url: &reqwest::Url, body: &[u8], clock_offset: i64) -> Result<HeaderMap> {
+    let timestamp = chrono::Utc::now().timestamp() + clock_offset;
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let signature = signature(&config.secret, timestamp, &nonce, method, &path, body)?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in [(TIMESTAMP_HEADER, timestamp.to_string()), (NONCE_HEADER, nonce), (SIGNATURE_HEADER, signature)] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from_str(&value)?);
+    }
+    Ok(headers)
+}
+
+/// Seconds the server's `Date` header is ahead of the local clock, if it sent one.
+pub fn clock_offset(headers: &HeaderMap) -> Option<i64> {
+    let date = headers.get(reqwest::header::DATE)?.to_str().ok()?;
+    let server = chrono::DateTime::parse_from_rfc2822(date).ok()?;
+    Some(server.timestamp() - chrono::Utc::now().timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_is_stable_and_covers_body() {
+        let a = signature("secret", 1700000000, "n1", "post", "/v1/chat/completions", b"{}").unwrap();
+        let b = signature("secret", 1700000000, "n1", "POST", "/v1/chat/completions", b"{}").unwrap();
+        let c = signature("secret", 1700000000, "n1", "POST", "/v1/chat/completions", b"{ }").unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.len(), 64);
+    }
+
+    #[test]
+    fn test_clock_offset_from_date_header() {
+        let mut headers = HeaderMap::new();
+        let ahead = chrono::Utc::now() + chrono::Duration::seconds(600);
+        headers.insert(reqwest::header::DATE, HeaderValue::from_str(&ahead.to_rfc2822()).unwrap());
+        let offset = clock_offset(&headers).unwrap();
+        assert!((599..=601).contains(&offset), "{}", offset);
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::Result;
+use crossterm::{
+    cursor, execute,
+    style::Stylize,
+    terminal::{Clear, ClearType},
+};
+use std::fs::{File, OpenOptions};
+use std::io::{stdout, Write};
+use std::path::Path;
+use termimad::MadSkin;
+use crate::llama::{LlamaError, Reply, StreamEvent};
+
+/// A consumer of one generation as it streams in: the terminal, a file, later remote clients.
+pub trait Sink: Send {
+    fn event(&mut self, event: &StreamEvent) -> Result<()>;
+
+    /// Called once after the last event, unless the stream failed.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Fans every event of a single provider call out to all sinks and assembles the reply.
+pub struct Dispatcher {
+    sinks: Vec<Box<dyn Sink>>,
+    reply: Reply,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Self {
+            sinks: Vec::new(),
+            reply: Reply::default(),
+        }
+    }
+
+    pub fn add(&mut self, sink: Box<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn dispatch(&mut self, event: StreamEvent) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.event(&event)?;
+        }
+        match event {
+            StreamEvent::Content(content) => self.reply.text.push_str(&content),
+            StreamEvent::Reasoning(thinking) => self.reply.thinking.push_str(&thinking),
+            StreamEvent::ToolCall(call) => self.reply.tool_calls.push(call),
+            StreamEvent::Usage(usage) => self.reply.usage.get_or_insert_with(Default::default).merge(usage),
+            StreamEvent::Finish(reason) => self.reply.finish_reason = Some(reason),
+            StreamEvent::Error(message) => return Err(LlamaError::StreamError(message).into()),
+            StreamEvent::Logprobs(tokens) => self.reply.logprobs.extend(tokens),
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Reply> {
+        for sink in &mut self.sinks {
+            sink.finish()?;
+        }
+        Ok(self.reply)
+    }
+}
+
+/// Live markdown rendering: plain text is printed as it arrives and the whole reply is
+/// re-rendered with the skin whenever a paragraph or code fence completes. Reasoning is
+/// shown as a single dimmed progress line that collapses once the answer starts.
+pub struct Terminal {
+    skin: MadSkin,
+    message: String,
+    block: String,
+    rendered_length: usize,
+    origin: (u16, u16),
+    thinking_words: usize,
+    thinking_open: bool,
+}
+
+/// The collapsed form of a reply's reasoning, shown above the answer.
+pub fn thinking_summary(words: usize) -> String {
+    format!("▸ Thought for {} words (/thinking to expand)", words)
+}
+
+impl Terminal {
+    pub fn start(skin: MadSkin) -> Result<Self> {
+        // Print the Assistant prefix and remember where the reply starts
+        print!("Assistant: ");
+        stdout().flush()?;
+        let origin = cursor::position()?;
+        println!();
+        Ok(Self {
+            skin,
+            message: String::new(),
+            block: String::new(),
+            rendered_length: 0,
+            origin,
+            thinking_words: 0,
+            thinking_open: false,
+        })
+    }
+
+    fn show_thinking(&mut self, text: &str) -> Result<()> {
+        self.thinking_words += text.split_whitespace().count();
+        self.thinking_open = true;
+        execute!(stdout(), cursor::MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+        print!("{}", format!("Thinking... ({} words)", self.thinking_words).dim());
+        Ok(())
+    }
+
+    // Replace the progress line with the summary; the answer renders below it
+    fn collapse_thinking(&mut self) -> Result<()> {
+        if !self.thinking_open {
+            return Ok(());
+        }
+        self.thinking_open = false;
+        execute!(stdout(), cursor::MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+        println!("{}", thinking_summary(self.thinking_words).dim());
+        self.origin = cursor::position()?;
+        Ok(())
+    }
+
+    fn render(&mut self) -> Result<()> {
+        execute!(
+            stdout(),
+            cursor::MoveTo(self.origin.0, self.origin.1),
+            Clear(ClearType::FromCursorDown)
+        )?;
+        self.skin.print_text(&self.message);
+        self.rendered_length = self.message.len();
+        Ok(())
+    }
+}
+
+impl Sink for Terminal {
+    fn event(&mut self, event: &StreamEvent) -> Result<()> {
+        let content = match event {
+            StreamEvent::Content(content) => content,
+            StreamEvent::Reasoning(thinking) => {
+                self.show_thinking(thinking)?;
+                stdout().flush()?;
+                return Ok(());
+            }
+            StreamEvent::Error(_) => {
+                println!();
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        self.collapse_thinking()?;
+        self.message.push_str(content);
+        self.block.push_str(content);
+
+        if content.contains("\n\n") || content.contains("```") {
+            self.render()?;
+            self.block.clear();
+            self.origin = cursor::position()?;
+        } else {
+            if self.block.len() == content.len() {
+                execute!(stdout(), cursor::MoveToColumn(0))?;
+            }
+            print!("{}", content);
+        }
+        stdout().flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.collapse_thinking()?;
+        if self.rendered_length < self.message.len() {
+            self.render()?;
+            println!();
+        }
+        Ok(())
+    }
+}
+
+/// Appends the reply text to a file as it streams in.
+pub struct TextFile {
+    file: File,
+}
+
+impl TextFile {
+    pub fn append(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl Sink for TextFile {
+    fn event(&mut self, event: &StreamEvent) -> Result<()> {
+        if let StreamEvent::Content(content) = event {
+            self.file.write_all(content.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.file.write_all(b"\n")?;
+        Ok(self.file.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Shared(Arc<Mutex<Vec<String>>>);
+
+    impl Sink for Shared {
+        fn event(&mut self, event: &StreamEvent) -> Result<()> {
+            if let StreamEvent::Content(content) = event {
+                self.0.lock().unwrap().push(content.clone());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_every_sink_sees_every_event() {
+        let (a, b) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.add(Box::new(Shared(a.clone())));
+        dispatcher.add(Box::new(Shared(b.clone())));
+        dispatcher.dispatch(StreamEvent::Content("Hel".into())).unwrap();
+        dispatcher.dispatch(StreamEvent::Content("lo".into())).unwrap();
+        dispatcher.dispatch(StreamEvent::Finish("stop".into())).unwrap();
+
+        let reply = dispatcher.finish().unwrap();
+        assert_eq!(reply.text, "Hello");
+        assert_eq!(reply.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(*a.lock().unwrap(), vec!["Hel", "lo"]);
+        assert_eq!(*a.lock().unwrap(), *b.lock().unwrap());
+    }
+
+    #[test]
+    fn test_error_event_fails_the_stream() {
+        let mut dispatcher = Dispatcher::new();
+        assert!(dispatcher.dispatch(StreamEvent::Error("overloaded".into())).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use serde_json::Value;
+use crate::llama::ToolCall;
+
+/// How a provider frames its streamed response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Server-sent events: `data:` lines, events end with a blank line
+    Sse,
+    /// One JSON document per line (Ollama)
+    Ndjson,
+}
+
+/// Incremental decoder that turns arbitrary network chunks into complete event payloads.
+///
+/// Chunks may end in the middle of a line, or even in the middle of a UTF-8 character,
+/// and one chunk may carry several events; bytes are buffered until a full line arrives.
+pub struct StreamDecoder {
+    framing: Framing,
+    buffer: Vec<u8>,
+    data: Vec<String>,
+    done: bool,
+    tool_calls: ToolCallAccumulator,
+}
+
+impl StreamDecoder {
+    pub fn new(framing: Framing) -> Self {
+        Self {
+            framing,
+            buffer: Vec::new(),
+            data: Vec::new(),
+            done: false,
+            tool_calls: ToolCallAccumulator::default(),
+        }
+    }
+
+    /// Tool calls of this stream that are still being assembled.
+    pub fn tool_calls(&mut self) -> &mut ToolCallAccumulator {
+        &mut self.tool_calls
+    }
+
+    /// Feed the next chunk and return every payload it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut payloads = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            self.handle_line(line.trim_end_matches(['\n', '\r']), &mut payloads);
+        }
+        payloads
+    }
+
+    /// Flush whatever is left once the connection closes.
+    pub fn finish(&mut self) -> Vec<String> {
+        let mut payloads = Vec::new();
+        if !self.buffer.is_empty() {
+            let line = String::from_utf8_lossy(&self.buffer).into_owned();
+            self.buffer.clear();
+            self.handle_line(line.trim_end_matches('\r'), &mut payloads);
+        }
+        self.dispatch(&mut payloads);
+        payloads
+    }
+
+    /// The tool call still being assembled once the stream is over. Servers that end the
+    /// stream without a finish reason never complete it otherwise.
+    pub fn unfinished_tool_call(&mut self) -> Option<ToolCall> {
+        self.tool_calls.finish()
+    }
+
+    /// Whether the stream announced its end with `[DONE]`.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn handle_line(&mut self, line: &str, payloads: &mut Vec<String>) {
+        match self.framing {
+            Framing::Ndjson => {
+                if !line.trim().is_empty() {
+                    payloads.push(line.to_string());
+                }
+            }
+            Framing::Sse => {
+                if line.is_empty() {
+                    self.dispatch(payloads);
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+                }
+                // Comments (":keep-alive"), event names, ids and retry hints carry no content
+            }
+        }
+    }
+
+    fn dispatch(&mut self, payloads: &mut Vec<String>) {
+        if self.data.is_empty() {
+            return;
+        }
+        let payload = self.data.join("\n");
+        self.data.clear();
+        if payload == "[DONE]" {
+            self.done = true;
+        } else {
+            payloads.push(payload);
+        }
+    }
+}
+
+/// Reassembles streamed tool calls. Each call arrives in fragments: the first carries
+/// its `index`, `id` and function name, the following ones pieces of the JSON arguments.
+/// Calls stream one after another, so a call is complete once the next index shows up
+/// or the stream reports its finish reason or ends.
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    current: Option<(usize, ToolCall)>,
+}
+
+impl ToolCallAccumulator {
+    /// Add the fragments of one payload's `tool_calls` array and return the calls they completed.
+    pub fn push(&mut self, deltas: &Value) -> Vec<ToolCall> {
+        let mut complete = Vec::new();
+        for (position, delta) in deltas.as_array().into_iter().flatten().enumerate() {
+            let index = delta["index"].as_u64().map_or(position, |index| index as usize);
+            if self.current.as_ref().is_some_and(|(current, _)| *current != index) {
+                complete.extend(self.finish());
+            }
+            let (_, call) = self.current.get_or_insert_with(|| (index, ToolCall::default()));
+            if let Some(id) = delta["id"].as_str() {
+                call.id = id.to_string();
+            }
+            if let Some(name) = delta["function"]["name"].as_str() {
+                call.name.push_str(name);
+            }
+            if let Some(arguments) = delta["function"]["arguments"].as_str() {
+                call.arguments.push_str(arguments);
+            }
+        }
+        complete
+    }
+
+    /// The call still being assembled, once the stream says it is done.
+    pub fn finish(&mut self) -> Option<ToolCall> {
+        self.current.take().map(|(_, call)| call)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_calls_complete_on_next_index_and_finish() {
+        let mut calls = ToolCallAccumulator::default();
+        let start = |index: u64, id: &str, name: &str| serde_json::json!([
+            {"index": index, "id": id, "type": "function", "function": {"name": name, "arguments": ""}}
+        ]);
+        let args = |index: u64, part: &str| serde_json::json!([{"index": index, "function": {"arguments": part}}]);
+
+        assert!(calls.push(&start(0, "call_a", "search")).is_empty());
+        assert!(calls.push(&args(0, "{\"q\":")).is_empty());
+        assert!(calls.push(&args(0, "\"rust\"}")).is_empty());
+        let first = calls.push(&start(1, "call_b", "open"));
+        assert_eq!(first.len(), 1);
+        assert_eq!((first[0].id.as_str(), first[0].name.as_str()), ("call_a", "search"));
+        assert_eq!(first[0].arguments, "{\"q\":\"rust\"}");
+
+        assert!(calls.push(&args(1, "{}")).is_empty());
+        assert_eq!(calls.finish().map(|call| call.id), Some("call_b".to_string()));
+        assert!(calls.finish().is_none());
+    }
+
+    #[test]
+    fn test_tool_call_without_finish_reason() {
+        let mut decoder = StreamDecoder::new(Framing::Sse);
+        let chunk = concat!(
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_a\",\"function\":{\"name\":\"search\",\"arguments\":\"{}\"}}]}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        for payload in decoder.push(chunk.as_bytes()) {
+            let json: Value = serde_json::from_str(&payload).unwrap();
+            assert!(decoder.tool_calls().push(&json["choices"][0]["delta"]["tool_calls"]).is_empty());
+        }
+        assert!(decoder.finish().is_empty());
+        assert_eq!(decoder.unfinished_tool_call().map(|call| call.id), Some("call_a".to_string()));
+        assert!(decoder.unfinished_tool_call().is_none());
+    }
+
+    #[test]
+    fn test_event_split_across_chunks() {
+        let mut decoder = StreamDecoder::new(Framing::Sse);
+        assert!(decoder.push(b"data: {\"a\":").is_empty());
+        assert!(decoder.push(b"1}\n").is_empty());
+        assert_eq!(decoder.push(b"\n"), vec!["{\"a\":1}"]);
+    }
+
+    #[test]
+    fn test_multiple_events_and_comments_in_one_chunk() {
+        let mut decoder = StreamDecoder::new(Framing::Sse);
+        let payloads = decoder.push(b": keep-alive\n\nevent: delta\ndata: one\n\ndata:two\r\n\r\ndata: [DONE]\n\n");
+        assert_eq!(payloads, vec!["one", "two"]);
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn test_multiline_data_and_unterminated_tail() {
+        let mut decoder = StreamDecoder::new(Framing::Sse);
+        assert!(decoder.push(b"data: first\ndata: second\n").is_empty());
+        assert_eq!(decoder.finish(), vec!["first\nsecond"]);
+    }
+
+    #[test]
+    fn test_utf8_split_inside_character() {
+        let bytes = "data: héllo\n\n".as_bytes();
+        let mut decoder = StreamDecoder::new(Framing::Sse);
+        assert!(decoder.push(&bytes[..8]).is_empty());
+        assert_eq!(decoder.push(&bytes[8..]), vec!["héllo"]);
+    }
+
+    #[test]
+    fn test_ndjson_lines() {
+        let mut decoder = StreamDecoder::new(Framing::Ndjson);
+        assert_eq!(decoder.push(b"{\"n\":1}\n{\"n\""), vec!["{\"n\":1}"]);
+        assert_eq!(decoder.push(b":2}\n"), vec!["{\"n\":2}"]);
+        assert!(decoder.finish().is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Result;
+use log::warn;
+use reqwest::{Response, Url};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::files;
+use crate::pool;
+use crate::session;
+
+/// Write the outgoing request as `<stamp>_request.json` and return the path the matching
+/// response should be recorded to. Query parameters that carry keys are redacted.
+pub fn record_request(dir: &Path, url: &Url, body: &[u8]) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let stamp = chrono::Local::now().format("%Y%m%d_%H%M%S%.3f");
+
+    let mut url = url.clone();
+    let query: Vec<(String, String)> = url.query_pairs()
+        .map(|(k, v)| {
+            let v = if k == "key" { "REDACTED".into() } else { v.into_owned() };
+            (k.into_owned(), v)
+        })
+        .collect();
+    if !query.is_empty() {
+        url.query_pairs_mut().clear().extend_pairs(&query);
+    }
+
+    let body: serde_json::Value = serde_json::from_slice(body)?;
+    let request = serde_json::json!({ "url": url.as_str(), "body": body });
+    let _lock = lock(dir)?;
+    files::write_atomic(&dir.join(format!("{}_request.json", stamp)), serde_json::to_string_pretty(&request)?)?;
+    Ok(dir.join(format!("{}_response.txt", stamp)))
+}
+
+/// Record something that happened between requests, like a provider switch, as `<stamp>_note.txt`.
+pub fn record_note(dir: &Path, note: &str) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let stamp = chrono::Local::now().format("%Y%m%d_%H%M%S%.3f");
+    let _lock = lock(dir)?;
+    files::write_atomic(&dir.join(format!("{}_note.txt", stamp)), format!("{}\n", note))?;
+    Ok(())
+}
+
+/// Copy the response (status line, headers, then the raw body exactly as it
+/// streams in) to `path` while the caller reads it as usual.
+pub fn record_response(response: Response, path: &Path) -> Response {
+    // Held while the file is created; the body is appended to a file that is already ours
+    let lock = path.parent().map(lock);
+    if let Some(Err(e)) = &lock {
+        warn!("Failed to lock transcripts for {}: {}", path.display(), e);
+    }
+    let mut file = match File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to create transcript {}: {}", path.display(), e);
+            return response;
+        }
+    };
+    let mut head = format!("{:?} {}\n", response.version(), response.status());
+    for (name, value) in response.headers() {
+        head.push_str(&format!("{}: {}\n", name, String::from_utf8_lossy(value.as_bytes())));
+    }
+    head.push('\n');
+
+    let mut failed = file.write_all(head.as_bytes()).is_err();
+    drop(lock);
+    pool::inspect_body(response, move |chunk| {
+        if !failed && file.write_all(chunk).is_err() {
+            warn!("Failed to write transcript, recording stopped for this response");
+            failed = true;
+        }
+    })
+}
+
+// Transcripts live in a directory of the conversation they record
+fn lock(dir: &Path) -> Result<File> {
+    let conversation_dir = dir.parent()
+        .ok_or_else(|| anyhow::anyhow!("No conversation for {}", dir.display()))?;
+    session::lock(conversation_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_redacts_key() {
+        let conversation_dir = std::env::temp_dir().join(format!("abot-transcript-{}", uuid::Uuid::new_v4()));
+        let dir = conversation_dir.join("transcripts");
+        let url = Url::parse("https://example.com/v1beta/models/m:generateContent?key=secret&alt=sse").unwrap();
+        let response_path = record_request(&dir, &url, b"{\"a\":1}").unwrap();
+        assert!(response_path.to_string_lossy().ends_with("_response.txt"));
+
+        let request = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let content = fs::read_to_string(request).unwrap();
+        assert!(content.contains("key=REDACTED&alt=sse"), "{}", content);
+        assert!(!content.contains("secret"));
+        fs::remove_dir_all(conversation_dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+/// Text sent for translation with every code block swapped for a placeholder,
+/// so the model can't translate identifiers or comments inside code.
+pub struct Masked {
+    pub text: String,
+    blocks: Vec<String>,
+}
+
+fn placeholder(index: usize) -> String {
+    format!("[[CODE_{}]]", index + 1)
+}
+
+pub fn mask_code(markdown: &str) -> Masked {
+    let mut text = String::new();
+    let mut blocks = Vec::new();
+    let mut block: Option<String> = None;
+    for line in markdown.lines() {
+        let fence = line.trim_start().starts_with("```");
+        match block.as_mut() {
+            Some(code) => {
+                code.push_str(line);
+                code.push('\n');
+                if fence {
+                    text.push_str(&placeholder(blocks.len()));
+                    text.push('\n');
+                    blocks.push(block.take().unwrap_or_default());
+                }
+            }
+            None if fence => block = Some(format!("{}\n", line)),
+            None => {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+    // An unclosed block still stays untranslated
+    if let Some(code) = block {
+        text.push_str(&placeholder(blocks.len()));
+        text.push('\n');
+        blocks.push(code);
+    }
+    Masked { text, blocks }
+}
+
+impl Masked {
+    /// Put the original code blocks back into the translated text.
+    pub fn unmask(&self, translated: &str) -> String {
+        let mut text = translated.to_string();
+        for (index, code) in self.blocks.iter().enumerate() {
+            text = text.replace(&placeholder(index), code.trim_end());
+        }
+        text
+    }
+
+    pub fn prompt(&self, language: &str) -> String {
+        format!(
+            "Translate the following markdown into {}. Keep the formatting, and keep every \
+             placeholder like [[CODE_1]] exactly as it is. Reply with the translation only.\n\n{}",
+            language, self.text
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_blocks_survive_translation() {
+        let reply = "Use a loop:\n\n```rust\n// count up\nfor i in 0..3 {}\n```\n\nDone.";
+        let masked = mask_code(reply);
+        assert_eq!(masked.text, "Use a loop:\n\n[[CODE_1]]\n\nDone.\n");
+
+        let translated = masked.unmask("Utilisez une boucle :\n\n[[CODE_1]]\n\nTerminé.");
+        assert_eq!(translated, "Utilisez une boucle :\n\n```rust\n// count up\nfor i in 0..3 {}\n```\n\nTerminé.");
+    }
+}
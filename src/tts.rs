@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use reqwest::{
+    Client,
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+};
+use serde_json::json;
+use log::debug;
+use crate::{secrets, TtsConfig};
+
+// OpenAI's speech endpoint rejects inputs longer than 4096 characters
+const MAX_INPUT_CHARS: usize = 4000;
+
+/// Turn markdown into something pleasant to listen to: code blocks are
+/// announced instead of read out symbol by symbol.
+pub fn speakable_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut in_code = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            if !in_code {
+                text.push_str("(code block omitted)\n");
+            }
+            in_code = !in_code;
+            continue;
+        }
+        if !in_code {
+            text.push_str(line.trim_start_matches(['#', '>', '*', '-', ' ']));
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Split text into pieces the TTS endpoint accepts, preferring paragraph breaks.
+pub fn split_input(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for paragraph in text.split("\n\n") {
+        if !current.is_empty() && current.chars().count() + paragraph.chars().count() > MAX_INPUT_CHARS {
+            chunks.push(std::mem::take(&mut current));
+        }
+        let mut rest: Vec<char> = paragraph.chars().collect();
+        while rest.len() > MAX_INPUT_CHARS {
+            let tail = rest.split_off(MAX_INPUT_CHARS);
+            chunks.push(rest.into_iter().collect());
+            rest = tail;
+        }
+        current.extend(rest);
+        current.push_str("\n\n");
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Synthesize one chunk of text through an OpenAI-compatible `/audio/speech` endpoint.
+pub async fn synthesize(client: &Client, config: &TtsConfig, text: &str) -> Result<Vec<u8>> {
+    let mut headers = HeaderMap::new();
+    if let Some(api_key) = &config.api_key {
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", secrets::resolve(api_key)?))?,
+        );
+    }
+
+    let body = json!({
+        "model": config.model,
+        "voice": config.voice,
+        "input": text,
+        "response_format": config.format,
+    });
+    debug!("TTS request for {} characters", text.len());
+
+    let response = client
+        .post(&config.api_url)
+        .headers(headers)
+        .json(&body)
+        .send()
+        .await
+        .context("Failed to connect to TTS service")?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error = response.text().await.unwrap_or_default();
+        return Err(anyhow::anyhow!("TTS request failed ({}): {}", status, error));
+    }
+
+    Ok(response.bytes().await?.to_vec())
+}
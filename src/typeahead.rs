@@ -0,0 +1,77 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::Stylize;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+// How often the reader checks whether the reply is done. Short, because the terminal
+// sink's cursor queries wait for the input reader this holds while polling
+const POLL: Duration = Duration::from_millis(10);
+
+/// Collects prompts typed while a reply is being generated, so they can be sent
+/// once it is done instead of getting mixed into the next prompt or lost.
+pub struct Typeahead {
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<(Vec<String>, String)>>,
+}
+
+/// Start collecting. Without a terminal on stdin (piped input) nothing is read here.
+pub fn start() -> Typeahead {
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader = std::io::stdin().is_terminal().then(|| {
+        let stop = stop.clone();
+        tokio::task::spawn_blocking(move || read_lines(&stop))
+    });
+    Typeahead { stop, reader }
+}
+
+impl Typeahead {
+    /// Stop collecting and return the complete lines typed so far, plus the line still
+    /// being typed, to carry on with at the next prompt.
+    pub async fn finish(self) -> (Vec<String>, String) {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.reader {
+            Some(reader) => reader.await.unwrap_or_default(),
+            None => (Vec::new(), String::new()),
+        }
+    }
+}
+
+fn read_lines(stop: &AtomicBool) -> (Vec<String>, String) {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    loop {
+        // Once asked to stop, still take what already arrived
+        let timeout = if stop.load(Ordering::SeqCst) { Duration::ZERO } else { POLL };
+        match event::poll(timeout) {
+            Ok(true) => {}
+            Ok(false) if stop.load(Ordering::SeqCst) => break,
+            Ok(false) => continue,
+            Err(_) => break,
+        }
+        let Ok(Event::Key(key)) = event::read() else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => {
+                let typed = std::mem::take(&mut line);
+                if !typed.trim().is_empty() {
+                    // Shown right away, between the lines of the reply still streaming
+                    println!("{}", format!("Pending: {}", typed.trim()).dim());
+                    lines.push(typed.trim().to_string());
+                }
+            }
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Char(c) => line.push(c),
+            _ => {}
+        }
+    }
+    (lines, line)
+}
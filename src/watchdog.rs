@@ -0,0 +1,109 @@
+use crossterm::style::Stylize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use crate::llama::LlamaClient;
+use crate::shutdown;
+
+const POLL: Duration = Duration::from_secs(3);
+
+enum State {
+    Up,
+    Down { detail: String, since: Instant },
+    /// Came back; reported once at the next prompt
+    Back { downtime: Duration },
+}
+
+/// Keeps polling a local backend once it stopped answering, so the prompt can show
+/// that it is down and requests go through again as soon as it is back.
+pub struct Watchdog {
+    name: String,
+    url: String,
+    state: Arc<Mutex<State>>,
+    poller: Option<JoinHandle<()>>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self { name: String::new(), url: String::new(), state: Arc::new(Mutex::new(State::Up)), poller: None }
+    }
+}
+
+impl Watchdog {
+    pub fn is_down(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), State::Down { .. })
+    }
+
+    /// Mark the provider's backend as down and poll it until it answers again.
+    pub fn down(&mut self, name: &str, client: LlamaClient, detail: String) {
+        if self.is_down() && self.name == name {
+            return;
+        }
+        self.stop();
+        self.name = name.to_string();
+        self.url = client.api_url().to_string();
+        *self.state.lock().unwrap() = State::Down { detail, since: Instant::now() };
+
+        let state = self.state.clone();
+        self.poller = Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(POLL).await;
+                let health = client.recheck().await;
+                let mut state = state.lock().unwrap();
+                match &mut *state {
+                    State::Down { since, .. } if health.available => {
+                        *state = State::Back { downtime: since.elapsed() };
+                        return;
+                    }
+                    State::Down { detail, .. } => *detail = health.detail,
+                    _ => return,
+                }
+            }
+        }));
+    }
+
+    /// Stop watching, e.g. after switching to another provider.
+    pub fn stop(&mut self) {
+        if let Some(poller) = self.poller.take() {
+            poller.abort();
+        }
+        *self.state.lock().unwrap() = State::Up;
+    }
+
+    /// The status line to show above the prompt: red for as long as the backend is down,
+    /// green once after it came back.
+    pub fn banner(&self) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        match &*state {
+            State::Up => None,
+            State::Down { detail, since } => Some(
+                format!(
+                    " {} at {} is down for {}s ({}), checking every {}s ",
+                    self.name, self.url, since.elapsed().as_secs(), detail, POLL.as_secs()
+                )
+                .white()
+                .on_red()
+                .to_string(),
+            ),
+            State::Back { downtime } => {
+                let line = format!("{} is back after {}s", self.name, downtime.as_secs()).green().to_string();
+                *state = State::Up;
+                Some(line)
+            }
+        }
+    }
+
+    /// Wait for the backend to come back. Returns false if Ctrl-C gave up first.
+    pub async fn wait(&self) -> bool {
+        let mut cancel = shutdown::streaming();
+        loop {
+            if !self.is_down() {
+                return true;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+                _ = cancel.cancelled() => return false,
+            }
+        }
+    }
+}
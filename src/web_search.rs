@@ -9,6 +9,10 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use futures::future::join_all;
 use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
 use crate::llama::{self, LlamaClient};
+use crate::files;
+use crate::session;
+use crate::pool;
+use crate::events::{AppEvent, Sender};
 use log::{debug, info,warn,error};
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CachedDocument {
@@ -23,24 +27,20 @@ pub struct WebSearch {
     cache_dir: PathBuf,
     conversation_id: String,
     max_results: usize,
+    max_page_tokens: usize,
     llama: LlamaClient,
     query: String,
     use_llama: bool,
 }
 
 impl WebSearch {
-    pub async fn new(conversation_id: &str, max_results: usize, llama: LlamaClient) -> Result<Self> {
-        let home_dir = dirs::home_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
-        let cache_dir = home_dir
-            .join(".cache")
-            .join("abot")
-            .join(conversation_id)
-            .join("web_cache");
-
-        if !cache_dir.exists() {
-            fs::create_dir_all(&cache_dir)?;
-        }
+    pub async fn new(
+        conversation_id: &str,
+        max_results: usize,
+        max_page_tokens: usize,
+        llama: LlamaClient,
+    ) -> Result<Self> {
+        let cache_dir = Self::cache_dir(conversation_id)?;
 
         // Test LLama availability
         let use_llama = match llama.test_availability().await {
@@ -56,16 +56,41 @@ impl WebSearch {
         };
 
         Ok(Self {
-            client: Client::new(),
+            client: pool::shared_client(),
             cache_dir,
             conversation_id: conversation_id.to_string(),
             max_results,
+            max_page_tokens,
             llama,
             query: String::new(),
             use_llama,
         })
     }
 
+    // Created here, so fetched pages can be written straight into it
+    fn cache_dir(conversation_id: &str) -> Result<PathBuf> {
+        let home_dir = dirs::home_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+        let cache_dir = home_dir
+            .join(".cache")
+            .join("abot")
+            .join(conversation_id)
+            .join("web_cache");
+
+        if !cache_dir.exists() {
+            fs::create_dir_all(&cache_dir)?;
+        }
+        Ok(cache_dir)
+    }
+
+    /// Cache pages for another conversation from now on: after /new, /fork, /load,
+    /// /import or a title renamed its directory.
+    pub fn set_conversation(&mut self, conversation_id: &str) -> Result<()> {
+        self.cache_dir = Self::cache_dir(conversation_id)?;
+        self.conversation_id = conversation_id.to_string();
+        Ok(())
+    }
+
     fn get_cache_path(&self, url: &str) -> PathBuf {
         // Encode URL to be filesystem safe
         let encoded_url = percent_encode(url.as_bytes(), NON_ALPHANUMERIC).to_string();
@@ -137,34 +162,13 @@ impl WebSearch {
             debug!("Content from {}: {}", url, content);
         }
 
-        // Modify the summary generation to check use_llama flag
-        let summary = if self.use_llama {
-            let summary_prompt = vec![llama::Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Please provide a brief, factual summary of the following text in 2-3 sentences:\n\n{}",
-                    content
-                ),
-            }];
-            
-            match self.llama.generate(&summary_prompt).await {
-                Ok(response) => {
-                    match LlamaClient::get_response_text(response).await {
-                        Ok(text) => text,
-                        Err(e) => {
-                            error!("Warning: Failed to parse LLM response: {}. Using fallback.", e);
-                            content.chars().take(500).collect::<String>().trim().to_string()
-                        }
-                    }
-                },
-                Err(e) => {
-                    error!("Warning: Failed to generate LLM summary: {}. Using fallback.", e);
-                    content.chars().take(500).collect::<String>().trim().to_string()
-                }
-            }
-        } else {
+        let summary = if !self.use_llama {
             // Simple fallback summary when LLama is not available
-            content.chars().take(500).collect::<String>().trim().to_string()
+            Self::fallback_summary(&content)
+        } else if llama::estimate_tokens(&content) > self.max_page_tokens {
+            self.summarize_chunked(&content).await
+        } else {
+            self.summarize(&content).await
         };
 
         let cached_doc = CachedDocument {
@@ -176,8 +180,11 @@ impl WebSearch {
             summary,
         };
 
-        // Save to cache
-        fs::write(
+        // Save to cache, under the lock of the conversation the cache belongs to
+        let conversation_dir = self.cache_dir.parent()
+            .ok_or_else(|| anyhow::anyhow!("No conversation for {}", self.cache_dir.display()))?;
+        let _lock = session::lock(conversation_dir)?;
+        files::write_atomic(
             &cache_path,
             serde_json::to_string_pretty(&cached_doc)?,
         )?;
@@ -185,9 +192,80 @@ impl WebSearch {
         Ok(cached_doc)
     }
 
-    pub async fn search(&mut self, query: &str) -> Result<String> {
+    fn fallback_summary(content: &str) -> String {
+        content.chars().take(500).collect::<String>().trim().to_string()
+    }
+
+    async fn summarize(&self, content: &str) -> String {
+        let summary_prompt = vec![llama::Message {
+            role: "user".to_string(),
+            content: format!(
+                "Please provide a brief, factual summary of the following text in 2-3 sentences:\n\n{}",
+                content
+            ),
+        }];
+
+        match self.llama.complete(&summary_prompt).await {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Warning: Failed to generate LLM summary: {}. Using fallback.", e);
+                Self::fallback_summary(content)
+            }
+        }
+    }
+
+    // Pages larger than the token budget are split on line boundaries, each chunk is
+    // summarized on its own and the partial summaries are condensed into one.
+    async fn summarize_chunked(&self, content: &str) -> String {
+        let chunk_chars = self.max_page_tokens.max(1) * 4;
+        let mut chunks = Vec::new();
+        let mut current = String::new();
+        for line in content.lines() {
+            if !current.is_empty() && current.len() + line.len() > chunk_chars {
+                chunks.push(std::mem::take(&mut current));
+            }
+            // A single oversized line still has to be cut somewhere
+            let mut rest = line;
+            while rest.len() > chunk_chars {
+                let mut cut = chunk_chars;
+                while !rest.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                chunks.push(rest[..cut].to_string());
+                rest = &rest[cut..];
+            }
+            current.push_str(rest);
+            current.push('\n');
+        }
+        if !current.trim().is_empty() {
+            chunks.push(current);
+        }
+
+        info!("Page exceeds {} tokens, summarizing {} chunks", self.max_page_tokens, chunks.len());
+        let mut partials = Vec::new();
+        for chunk in &chunks {
+            partials.push(self.summarize(chunk).await);
+        }
+        let summary = if partials.len() > 1 {
+            self.summarize(&partials.join("\n")).await
+        } else {
+            partials.concat()
+        };
+
+        format!(
+            "(page exceeded {} tokens, summarized from {} chunks) {}",
+            self.max_page_tokens,
+            chunks.len(),
+            summary
+        )
+    }
+
+    /// Search and summarize the top results. Progress goes to `events`, which is
+    /// dropped when the search finishes.
+    pub async fn search(&mut self, query: &str, events: Sender) -> Result<String> {
         #[cfg(debug_assertions)]
         debug!("Starting search with query: {}", query);
+        let _ = events.send(AppEvent::ToolStarted(format!("web search for '{}'", query)));
 
         //save the query to self
         self.query = query.to_string();
@@ -230,7 +308,7 @@ impl WebSearch {
             let encoded_url = result
                 .select(&url_selector)
                 .next()
-                .and_then(|el| Some(el.text().collect::<String>()))
+                .map(|el| el.text().collect::<String>())
                 .unwrap_or_default();
 
             #[cfg(debug_assertions)]
@@ -275,6 +353,12 @@ impl WebSearch {
             debug!("Limiting results to max_results: {}", self.max_results);
         }
         
+        let _ = events.send(AppEvent::SearchProgress(format!(
+            "Found {} results, reading the top {}...",
+            search_results.len(),
+            search_results.len().min(self.max_results)
+        )));
+
         // featch and cache all URLs (limit to first max_results) in search results.
         let fetch_futures: Vec<_> = search_results.iter()
             .take(self.max_results)
@@ -287,7 +371,7 @@ impl WebSearch {
         // Fetch all URLs concurrently
         let results = join_all(fetch_futures).await;
         
-        println!("Processing search results...");
+        let _ = events.send(AppEvent::SearchProgress("Processing search results...".to_string()));
         
         // Process results
         let summaries: String = results.into_iter()
@@ -306,7 +390,7 @@ impl WebSearch {
         #[cfg(debug_assertions)]
         debug!("Final processed summaries length: {} bytes", summaries.len());
 
-        println!("Search completed successfully!");
+        let _ = events.send(AppEvent::SearchProgress("Search completed successfully!".to_string()));
         Ok(summaries)
     }
 } 
\ No newline at end of file